@@ -0,0 +1,51 @@
+//! Throughput baseline for [`StateMachine::next_state`].
+//!
+//! Measures a representative customer session — swipe, PIN entry (the
+//! hashing path, allocation-free since `hash_keys` started streaming the
+//! digit bytes), amount entry and dispense — so future optimizations
+//! have a number to beat.
+
+use atm::{hash_pin, Action, Atm, Key, StateMachine};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+/// One whole session: authenticate, withdraw $140, and a trailing tick.
+fn session_actions() -> Vec<Action> {
+    let pin = [Key::One, Key::Two, Key::Three, Key::Four];
+    let mut actions = vec![Action::SwipeCard(hash_pin(&pin))];
+    actions.extend(pin.iter().map(|k| Action::PressKey(*k)));
+    actions.push(Action::PressKey(Key::Enter));
+    actions.extend(
+        [Key::One, Key::Four, Key::Zero, Key::Enter]
+            .iter()
+            .map(|k| Action::PressKey(*k)),
+    );
+    actions.push(Action::Tick);
+    actions
+}
+
+fn bench_next_state(c: &mut Criterion) {
+    let actions = session_actions();
+    c.bench_function("full_session", |b| {
+        b.iter(|| {
+            let mut atm = Atm::new(1_000_000);
+            for action in &actions {
+                atm = Atm::next_state(black_box(&atm), action);
+            }
+            atm
+        })
+    });
+
+    // The PIN check alone, isolated so hashing regressions show up
+    // without the session noise.
+    let pin = [Key::One, Key::Two, Key::Three, Key::Four];
+    let mut entering = Atm::next_state(&Atm::new(100), &Action::SwipeCard(hash_pin(&pin)));
+    for key in pin {
+        entering = Atm::next_state(&entering, &Action::PressKey(key));
+    }
+    c.bench_function("pin_check", |b| {
+        b.iter(|| Atm::next_state(black_box(&entering), &Action::PressKey(Key::Enter)))
+    });
+}
+
+criterion_group!(benches, bench_next_state);
+criterion_main!(benches);
@@ -0,0 +1,193 @@
+//! A full-screen terminal driver for the [`atm`] state machine.
+//!
+//! Draws the customer screen and the keypad with `ratatui` and feeds each
+//! keypress through [`Atm::transition`], so all the machine logic lives in
+//! the library — this binary only translates terminal events to [`Action`]s
+//! and renders what the machine says back.
+//!
+//! Keys: digits, `.` and `Enter` go to the keypad; `s` swipes the demo card
+//! (PIN 1234), `n` feeds a $10 note into the deposit slot, `t` advances the
+//! clock one second, `q` or `Esc` quits.
+
+use std::io;
+
+use atm::{hash_pin, Action, Atm, Key};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::{Frame, Terminal};
+
+/// The PIN behind the demo card that `s` swipes.
+const DEMO_PIN: [Key; 4] = [Key::One, Key::Two, Key::Three, Key::Four];
+
+/// Everything the screen needs: the machine plus a little echo state the
+/// library deliberately keeps private (what the customer has typed).
+struct App {
+    atm: Atm,
+    /// Characters typed since the last `Enter`, echoed on screen.
+    entered: String,
+    /// Whether the echo is a PIN and must be masked.
+    entering_pin: bool,
+    /// The customer-facing message of the last effect, if any.
+    last_message: String,
+}
+
+impl App {
+    fn new() -> Self {
+        App {
+            atm: Atm::new(500),
+            entered: String::new(),
+            entering_pin: false,
+            last_message: String::new(),
+        }
+    }
+
+    /// Feed `action` through the machine, keeping the last effect's
+    /// message around for the screen.
+    fn apply(&mut self, action: Action) {
+        let (next, effect) = Atm::transition(&self.atm, &action);
+        self.atm = next;
+        if let Some(effect) = effect {
+            self.last_message = self.atm.message(&effect);
+        }
+    }
+
+    /// Translate one terminal key. Returns `true` when the app should
+    /// quit.
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Char('s') => {
+                self.apply(Action::SwipeCard(hash_pin(&DEMO_PIN)));
+                self.entered.clear();
+                self.entering_pin = true;
+            }
+            KeyCode::Char('n') => self.apply(Action::InsertNote(10)),
+            KeyCode::Char('t') => self.apply(Action::Tick),
+            KeyCode::Enter => {
+                self.apply(Action::PressKey(Key::Enter));
+                self.entered.clear();
+                self.entering_pin = false;
+            }
+            KeyCode::Char(c) => {
+                if let Some(key) = keypad_key(c) {
+                    self.apply(Action::PressKey(key));
+                    self.entered.push(c);
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// The lines shown on the customer screen, top to bottom. Kept free
+    /// of any `ratatui` types so tests can read the screen directly.
+    fn screen_lines(&self) -> Vec<String> {
+        let echo = if self.entering_pin {
+            "*".repeat(self.entered.len())
+        } else {
+            self.entered.clone()
+        };
+        vec![
+            self.atm.prompt().to_string(),
+            format!("> {echo}"),
+            self.last_message.clone(),
+            format!("Cash in machine: ${}", self.atm.cash_inside()),
+        ]
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(6),
+                Constraint::Length(6),
+                Constraint::Min(1),
+            ])
+            .split(frame.size());
+        let screen = Paragraph::new(self.screen_lines().join("\n"))
+            .block(Block::default().title("ATM").borders(Borders::ALL));
+        frame.render_widget(screen, chunks[0]);
+        let keypad = Paragraph::new(" 1 2 3\n 4 5 6\n 7 8 9\n . 0 ⏎")
+            .block(Block::default().title("Keypad").borders(Borders::ALL));
+        frame.render_widget(keypad, chunks[1]);
+        let help =
+            Paragraph::new("s swipe demo card (PIN 1234) · n insert $10 · t tick · q quit");
+        frame.render_widget(help, chunks[2]);
+    }
+}
+
+/// The keypad key entered by typing `c`, if any.
+fn keypad_key(c: char) -> Option<Key> {
+    match c {
+        '0' => Some(Key::Zero),
+        '1' => Some(Key::One),
+        '2' => Some(Key::Two),
+        '3' => Some(Key::Three),
+        '4' => Some(Key::Four),
+        '5' => Some(Key::Five),
+        '6' => Some(Key::Six),
+        '7' => Some(Key::Seven),
+        '8' => Some(Key::Eight),
+        '9' => Some(Key::Nine),
+        '.' => Some(Key::Dot),
+        _ => None,
+    }
+}
+
+fn main() -> io::Result<()> {
+    enable_raw_mode()?;
+    crossterm::execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    let mut app = App::new();
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && app.handle_key(key.code) {
+                break;
+            }
+        }
+    }
+    disable_raw_mode()?;
+    crossterm::execute!(io::stdout(), LeaveAlternateScreen)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn app_starts_on_the_welcome_screen() {
+        let app = App::new();
+        assert_eq!(app.screen_lines()[0], "Please swipe your card");
+    }
+
+    #[test]
+    fn keypad_drives_a_whole_session() {
+        let mut app = App::new();
+        app.handle_key(KeyCode::Char('s'));
+        for c in "1234".chars() {
+            app.handle_key(KeyCode::Char(c));
+        }
+        // The PIN echo is masked on screen.
+        assert_eq!(app.screen_lines()[1], "> ****");
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.screen_lines()[0], "Enter amount");
+        for c in "30".chars() {
+            app.handle_key(KeyCode::Char(c));
+        }
+        app.handle_key(KeyCode::Enter);
+        assert_eq!(app.atm.cash_inside(), 470);
+        assert!(app.screen_lines()[2].contains("$30"));
+    }
+
+    #[test]
+    fn q_quits() {
+        assert!(App::new().handle_key(KeyCode::Char('q')));
+    }
+}
@@ -0,0 +1,6150 @@
+//! An ATM (automated teller machine) modelled as a finite state machine.
+//!
+//! The machine starts out `Waiting` for a card. Swiping a card — whose
+//! magstripe carries the expected PIN hash — moves it to `Authenticating`;
+//! the customer keys in their PIN and presses `Enter`. A correct PIN moves
+//! the machine to `Authenticated`, where keying in an amount and pressing
+//! `Enter` dispenses cash and returns to `Waiting`. Too many failed PIN
+//! attempts lock the machine.
+//!
+//! Transitions are pure: [`StateMachine::next_state`] consumes nothing and
+//! returns a fresh state, which keeps every step easy to test in isolation.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+mod script;
+mod shared;
+mod store;
+mod typestate;
+
+pub use script::{validate_script, ParseError};
+pub use shared::SharedAtm;
+pub use store::{FileStore, StateStore};
+pub use typestate::{AtmAuthenticated, AtmAuthenticating, AtmWaiting};
+
+/// An abstract finite state machine: a pure transition function over states.
+///
+/// Implementors pick a state type and a transition (input) type; the machine
+/// itself carries no data, which is why `next_state` is an associated
+/// function rather than a method.
+pub trait StateMachine {
+    type State;
+    type Transition;
+
+    /// Compute the state that follows `starting_state` when `transition`
+    /// is applied. Must not mutate anything.
+    fn next_state(starting_state: &Self::State, transition: &Self::Transition) -> Self::State;
+
+    /// Whether `state` is an accepting ("goal") state of the machine, for
+    /// generic algorithms that reason about reachability of goals.
+    /// Machines without a notion of acceptance keep the default: nothing
+    /// accepts.
+    fn is_accepting(_state: &Self::State) -> bool {
+        false
+    }
+}
+
+/// An object-safe sibling of [`StateMachine`].
+///
+/// The main trait's associated types and static methods rule out trait
+/// objects; this one is concretely typed over [`Action`] and [`Effect`]
+/// and steps in place, so heterogeneous drivers can hold machines as
+/// `Box<dyn DynStateMachine>`.
+pub trait DynStateMachine {
+    /// Apply `action` to this machine, returning any effect it produced.
+    fn step(&mut self, action: &Action) -> Option<Effect>;
+
+    /// Whether the machine is currently in an accepting state.
+    fn accepting(&self) -> bool;
+}
+
+impl DynStateMachine for Atm {
+    fn step(&mut self, action: &Action) -> Option<Effect> {
+        self.advance(action)
+    }
+
+    fn accepting(&self) -> bool {
+        Atm::is_accepting(self)
+    }
+}
+
+/// Check whether two states are bisimilar up to `depth` steps: they are
+/// already equal, or every transition in `alphabet` leads them to states
+/// that are themselves equivalent one level shallower.
+///
+/// A teaching tool for automata: the cost is `alphabet.len()^depth`, so
+/// keep the depth small.
+pub fn states_equivalent<SM: StateMachine>(
+    a: &SM::State,
+    b: &SM::State,
+    alphabet: &[SM::Transition],
+    depth: usize,
+) -> bool
+where
+    SM::State: PartialEq,
+{
+    if a == b {
+        return true;
+    }
+    if depth == 0 {
+        return false;
+    }
+    alphabet.iter().all(|t| {
+        states_equivalent::<SM>(&SM::next_state(a, t), &SM::next_state(b, t), alphabet, depth - 1)
+    })
+}
+
+/// Check whether `state` is absorbing under `alphabet`: every listed
+/// transition leads straight back to it.
+///
+/// A teaching tool for automata: it finds deadlocked ("stuck") states,
+/// like a locked ATM offered only customer actions. Whether a state
+/// truly absorbs depends on the alphabet — the same locked machine is
+/// not absorbing once operator actions are on the list.
+pub fn is_absorbing<SM: StateMachine>(state: &SM::State, alphabet: &[SM::Transition]) -> bool
+where
+    SM::State: PartialEq,
+{
+    alphabet.iter().all(|t| SM::next_state(state, t) == *state)
+}
+
+/// Replay `log` from `start` and check the machine lands exactly on
+/// `expected_final` — an audit and reproducibility teaching tool:
+/// transitions are pure, so the same log from the same state must
+/// always produce the same machine.
+pub fn verify_log(start: &Atm, log: &[Action], expected_final: &Atm) -> bool {
+    let mut atm = start.clone();
+    for action in log {
+        atm = Atm::next_state(&atm, action);
+    }
+    atm == *expected_final
+}
+
+/// A key on the ATM keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Key {
+    Zero,
+    One,
+    Two,
+    Three,
+    Four,
+    Five,
+    Six,
+    Seven,
+    Eight,
+    Nine,
+    /// Decimal separator for cent entry; only meaningful on machines with
+    /// a nonzero amount scale.
+    Dot,
+    Enter,
+}
+
+impl Key {
+    /// Every key on the keypad, for building UIs and exhaustive or random
+    /// keystroke tests. Keep in sync with the enum when adding keys.
+    pub fn all() -> &'static [Key] {
+        &[
+            Key::Zero,
+            Key::One,
+            Key::Two,
+            Key::Three,
+            Key::Four,
+            Key::Five,
+            Key::Six,
+            Key::Seven,
+            Key::Eight,
+            Key::Nine,
+            Key::Dot,
+            Key::Enter,
+        ]
+    }
+
+    /// The ten digit keys in face-value order — the un-scrambled keypad
+    /// row, and the identity layout for [`Action::PressPosition`].
+    pub fn digit_row() -> [Key; 10] {
+        [
+            Key::Zero,
+            Key::One,
+            Key::Two,
+            Key::Three,
+            Key::Four,
+            Key::Five,
+            Key::Six,
+            Key::Seven,
+            Key::Eight,
+            Key::Nine,
+        ]
+    }
+
+    /// The stock key→digit assignment — each digit key enters its face
+    /// value. The seed for custom layouts via [`Atm::with_digit_map`].
+    pub fn standard_digit_map() -> HashMap<Key, u8> {
+        Key::all()
+            .iter()
+            .filter_map(|key| key.digit().map(|digit| (*key, digit as u8)))
+            .collect()
+    }
+
+    /// The key that enters `digit`, the inverse of [`Key::digit`].
+    fn from_digit(digit: u32) -> Option<Key> {
+        match digit {
+            0 => Some(Key::Zero),
+            1 => Some(Key::One),
+            2 => Some(Key::Two),
+            3 => Some(Key::Three),
+            4 => Some(Key::Four),
+            5 => Some(Key::Five),
+            6 => Some(Key::Six),
+            7 => Some(Key::Seven),
+            8 => Some(Key::Eight),
+            9 => Some(Key::Nine),
+            _ => None,
+        }
+    }
+
+    /// The digit this key enters, or `None` for function keys like `Enter`.
+    fn digit(self) -> Option<u64> {
+        match self {
+            Key::Zero => Some(0),
+            Key::One => Some(1),
+            Key::Two => Some(2),
+            Key::Three => Some(3),
+            Key::Four => Some(4),
+            Key::Five => Some(5),
+            Key::Six => Some(6),
+            Key::Seven => Some(7),
+            Key::Eight => Some(8),
+            Key::Nine => Some(9),
+            Key::Dot | Key::Enter => None,
+        }
+    }
+}
+
+/// A strategy for hashing PIN keystrokes.
+///
+/// The default is the fast, unkeyed [`hash_pin`]; institutions that want a
+/// keyed or salted digest inject their own via [`Atm::with_pin_hasher`].
+/// The card's hash must of course come from the same hasher.
+pub trait PinHasher {
+    fn hash(&self, keys: &[Key]) -> u64;
+}
+
+/// The stock hasher: [`hash_pin`] behind the [`PinHasher`] trait.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DefaultPinHasher;
+
+impl PinHasher for DefaultPinHasher {
+    fn hash(&self, keys: &[Key]) -> u64 {
+        hash_pin(keys)
+    }
+}
+
+/// Shared handle to the machine's PIN hasher.
+///
+/// The hasher is opaque configuration: cloning a machine shares it, and
+/// state comparison ignores it entirely (two machines differing only in
+/// hasher compare equal).
+#[derive(Clone)]
+struct HasherHandle(Arc<dyn PinHasher + Send + Sync>);
+
+impl Default for HasherHandle {
+    fn default() -> Self {
+        HasherHandle(Arc::new(DefaultPinHasher))
+    }
+}
+
+impl fmt::Debug for HasherHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PinHasher")
+    }
+}
+
+impl PartialEq for HasherHandle {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for HasherHandle {}
+
+/// A source of wall-clock time, in epoch seconds.
+///
+/// The machine itself stays pure — time only moves via [`Action::Tick`]
+/// and [`Action::SetClock`] — but drivers need somewhere to read time
+/// from. Injecting a clock lets the binary use the real one and tests
+/// use a hand-cranked one, with [`Atm::sync_clock`] bridging either into
+/// the action stream.
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+/// The real wall clock, for binaries and long-running drivers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// A clock tests crank by hand. Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<std::sync::atomic::AtomicU64>);
+
+impl ManualClock {
+    /// A clock reading `now` seconds.
+    pub fn at(now: u64) -> Self {
+        ManualClock(Arc::new(std::sync::atomic::AtomicU64::new(now)))
+    }
+
+    /// Move the clock forward by `seconds`.
+    pub fn advance(&self, seconds: u64) {
+        self.0
+            .fetch_add(seconds, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.0.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+/// Shared handle to the machine's clock.
+///
+/// Like the PIN hasher, the clock is opaque configuration: cloning a
+/// machine shares it, and state comparison ignores it entirely.
+#[derive(Clone)]
+struct ClockHandle(Arc<dyn Clock + Send + Sync>);
+
+impl Default for ClockHandle {
+    fn default() -> Self {
+        ClockHandle(Arc::new(SystemClock))
+    }
+}
+
+impl fmt::Debug for ClockHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Clock")
+    }
+}
+
+impl PartialEq for ClockHandle {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ClockHandle {}
+
+/// Where account balances live when they are shared between machines —
+/// a fleet of ATMs drawing on one bank ledger.
+///
+/// Implementations use interior mutability: a settle happens through
+/// `&self`, so one provider can sit behind several machines at once.
+pub trait BalanceProvider {
+    /// The balance behind `card`, if the ledger knows the card.
+    fn balance(&self, card: u64) -> Option<u64>;
+    /// Take `amount` out of `card`'s balance (saturating at zero).
+    fn debit(&self, card: u64, amount: u64);
+    /// Add `amount` to `card`'s balance, if the ledger knows the card.
+    fn credit(&self, card: u64, amount: u64);
+}
+
+/// The stock shared ledger: one balance map behind a lock, cloned
+/// cheaply so any number of machines (and the test harness) can hold it.
+#[derive(Clone, Default)]
+pub struct SharedLedger(Arc<Mutex<HashMap<u64, u64>>>);
+
+impl SharedLedger {
+    pub fn new() -> Self {
+        SharedLedger::default()
+    }
+
+    /// Register (or overwrite) the balance behind `card`.
+    pub fn open_account(&self, card: u64, balance: u64) {
+        self.0.lock().expect("ledger lock poisoned").insert(card, balance);
+    }
+}
+
+impl BalanceProvider for SharedLedger {
+    fn balance(&self, card: u64) -> Option<u64> {
+        self.0.lock().expect("ledger lock poisoned").get(&card).copied()
+    }
+
+    fn debit(&self, card: u64, amount: u64) {
+        if let Some(balance) = self.0.lock().expect("ledger lock poisoned").get_mut(&card) {
+            *balance = balance.saturating_sub(amount);
+        }
+    }
+
+    fn credit(&self, card: u64, amount: u64) {
+        if let Some(balance) = self.0.lock().expect("ledger lock poisoned").get_mut(&card) {
+            *balance += amount;
+        }
+    }
+}
+
+/// Shared handle to the machine's ledger, when one is injected.
+///
+/// Like the PIN hasher, it is opaque configuration: cloning a machine
+/// shares it, and state comparison ignores it entirely.
+#[derive(Clone)]
+struct LedgerHandle(Arc<dyn BalanceProvider + Send + Sync>);
+
+impl fmt::Debug for LedgerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BalanceProvider")
+    }
+}
+
+impl PartialEq for LedgerHandle {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for LedgerHandle {}
+
+/// An injected business rule consulted before any dispense.
+///
+/// Rules carry regional regulation and institution policy the core
+/// machine should not hardcode; every registered rule must pass before
+/// cash moves. A failing rule refuses the withdrawal exactly like the
+/// built-in checks: the session ends quietly.
+pub trait WithdrawalRule {
+    fn check(&self, amount: u64, state: &Atm) -> Result<(), AtmError>;
+}
+
+/// Shared handle to one injected rule.
+///
+/// Like the PIN hasher, rules are opaque configuration: cloning a
+/// machine shares them, and state comparison ignores them entirely.
+#[derive(Clone)]
+struct RuleHandle(Arc<dyn WithdrawalRule + Send + Sync>);
+
+impl fmt::Debug for RuleHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WithdrawalRule")
+    }
+}
+
+impl PartialEq for RuleHandle {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for RuleHandle {}
+
+/// Hash a keystroke sequence: the digits, fed straight into the standard
+/// library's [`DefaultHasher`] — good enough for an exercise, not for
+/// production.
+///
+/// Non-digit keys (`Dot`, `Enter`) are skipped, so only the digits keyed
+/// determine the hash. No intermediate `String` is built, but the digest
+/// is byte-for-byte the one hashing the digit `String` used to produce —
+/// the ASCII digits stream by, closed with the `0xff` terminator
+/// `str::hash` writes — so card hashes and persisted state minted before
+/// the allocation was removed keep authenticating.
+pub fn hash_keys(keys: &[Key]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for digit in keys.iter().filter_map(|k| k.digit()) {
+        hasher.write(&[b'0' + digit as u8]);
+    }
+    hasher.write_u8(0xff);
+    hasher.finish()
+}
+
+/// Hash a PIN keystroke sequence — the established name for
+/// [`hash_keys`], which cards and tests mint their hashes with.
+pub fn hash_pin(keys: &[Key]) -> u64 {
+    hash_keys(keys)
+}
+
+/// Everything that can happen to the machine from the outside world.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// A card was swiped; the magstripe carries the expected PIN hash.
+    SwipeCard(u64),
+    /// A card was tapped. Like a swipe, but contactless: no PIN is asked
+    /// for, and withdrawals are capped at the tap limit.
+    TapCard(u64),
+    /// A swipe whose magstripe also carries the card's expiry (epoch
+    /// seconds). Expired cards are refused against the machine clock.
+    SwipeCardFull { number: u64, expiry: u64 },
+    /// A degraded reader got only a partial read. A matching second
+    /// partial read inside the re-swipe window completes it; otherwise
+    /// the attempt quietly lapses.
+    PartialSwipe(u64),
+    /// A chip card was dipped into the reader. Unlike a swipe the card
+    /// stays in the machine for the whole session, which runs until
+    /// [`Action::RemoveCard`] takes it back.
+    InsertChipCard(u64),
+    /// The customer pulled their chip card; the session ends.
+    RemoveCard,
+    /// A keypad key was pressed.
+    PressKey(Key),
+    /// A screen position (0-9) was touched; which digit that enters
+    /// depends on the session's keypad layout, scrambled machines
+    /// reshuffle it each swipe.
+    PressPosition(u8),
+    /// A whole PIN attempt arrived at once, as scripts and simulations
+    /// like to send it: each key is pressed in order, then `Enter`.
+    EnterPin(Vec<Key>),
+    /// One second of wall-clock time passed.
+    Tick,
+    /// The machine's clock was set to an absolute time (epoch seconds).
+    /// Timeouts are computed against it just as with relative ticks.
+    SetClock(u64),
+    /// Midnight: the daily withdrawal accounting rolls over.
+    NewDay,
+    /// An operator zeroed the service counters. Supervisor-only.
+    ResetCounters,
+    /// An operator hotlisted a card. Supervisor-only.
+    BlockCard(u64),
+    /// An operator approved a one-time higher per-withdrawal limit for
+    /// the next customer withdrawal. Supervisor-only.
+    ApproveHigherLimit(u64),
+    /// An operator changed the per-day withdrawal allowance. The new
+    /// limit stands until changed again; `NewDay` only resets the
+    /// running total. Supervisor-only.
+    SetDailyLimit(u64),
+    /// An operator asked for the machine's cash position — unlike a
+    /// customer balance inquiry, this reports the machine's own
+    /// holdings. Supervisor-only.
+    OperatorBalance,
+    /// The operator's physical key switch was turned on (`true`) or off,
+    /// unlocking supervisor operations and suspending customer service.
+    MaintenanceKey(bool),
+    /// The customer picked a display language on the welcome screen.
+    SetLanguage(Language),
+    /// The customer asked for a mini-statement of their last N
+    /// transactions.
+    MiniStatement(usize),
+    /// The customer chose which of their accounts this session's
+    /// transactions move money in. Sessions start on checking.
+    SelectAccount(AccountType),
+    /// The customer moved money from their selected account to another
+    /// card's account. No cash is involved.
+    Transfer { to: u64, amount: u64 },
+    /// The customer chose whether this session's transactions should
+    /// print receipts.
+    SetReceiptPreference(bool),
+    /// The customer asked for a balance printout instead of cash; the
+    /// session ends with the receipt.
+    BalanceReceipt,
+    /// The bank network stopped responding mid-operation.
+    AuthTimeout,
+    /// The customer took their card back from the reader.
+    TakeCard,
+    /// A UI asked whether a card is sitting in the reader.
+    CardStatus,
+    /// The customer asked for an exact number of one denomination —
+    /// "four $5 bills" — instead of keying an amount.
+    WithdrawBills { denomination: u64, count: u64 },
+    /// The customer pre-authorized an amount: the cash is reserved —
+    /// counted out of what withdrawals may take — but not dispensed.
+    HoldFunds(u64),
+    /// The pre-authorized amount was captured: the cash leaves the
+    /// machine as a completed transaction.
+    CaptureHold,
+    /// The pre-authorization was cancelled; the reserved cash is
+    /// available again.
+    ReleaseHold,
+    /// The customer fed one bank note into the deposit slot.
+    InsertNote(u64),
+    /// The customer backed out of an in-progress deposit; the inserted
+    /// notes come back uncredited.
+    CancelDeposit,
+    /// The customer asked for the last transaction's receipt again,
+    /// shortly after it printed.
+    ReprintReceipt,
+    /// A logger asked which machine this is.
+    Identify,
+    /// A keypad key's contact stuck closed: every subsequent keypress
+    /// also registers the stuck key.
+    StuckKey(Key),
+    /// The stuck keypad key was freed.
+    UnstickKey,
+    /// The cash dispenser mechanism jammed; withdrawals fail until an
+    /// operator clears it.
+    JamDispenser,
+    /// An operator cleared a dispenser jam. Supervisor-only.
+    ClearJam,
+    /// An operator zeroed the failed-PIN counter, unlocking a locked
+    /// machine. Supervisor-only.
+    ClearFailedAttempts,
+    /// An operator switched demo/training mode on or off. In training
+    /// mode every transition and effect happens, but no real cash moves.
+    /// Supervisor-only.
+    SetTrainingMode(bool),
+    /// Mains power failed. The machine drops to a safe Waiting state,
+    /// discarding partial entries (never cash).
+    PowerLoss,
+    /// Mains power came back; the machine resumes service.
+    PowerRestore,
+}
+
+/// Display languages the machine can speak.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Language {
+    #[default]
+    English,
+    Spanish,
+}
+
+/// What a bank demands of a PIN at provisioning time.
+///
+/// The machine itself never sees a provisioning step — cards arrive
+/// carrying only a hash — so these checks run wherever PINs are chosen.
+/// Note that this crate's favourite test PIN, 1234, fails any policy
+/// that forbids sequential digits; the default policy is deliberately
+/// lax enough to keep it working.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinPolicy {
+    /// Fewest digits allowed.
+    pub min_length: usize,
+    /// Refuse PINs that are one digit repeated, like 1111.
+    pub forbid_repeated: bool,
+    /// Refuse PINs whose digits run up or down by one, like 1234 or
+    /// 4321.
+    pub forbid_sequential: bool,
+}
+
+impl Default for PinPolicy {
+    fn default() -> Self {
+        PinPolicy {
+            min_length: 4,
+            forbid_repeated: false,
+            forbid_sequential: false,
+        }
+    }
+}
+
+/// Why a PIN was refused at provisioning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinError {
+    /// The PIN has fewer digits than the policy's minimum.
+    TooShort { minimum: usize },
+    /// A non-digit key (like `Dot` or `Enter`) has no place in a PIN.
+    NotADigit,
+    /// The PIN is one digit repeated.
+    RepeatedDigits,
+    /// The PIN's digits run straight up or down.
+    SequentialDigits,
+}
+
+impl fmt::Display for PinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinError::TooShort { minimum } => {
+                write!(f, "PIN must be at least {minimum} digits")
+            }
+            PinError::NotADigit => write!(f, "PIN may only contain digits"),
+            PinError::RepeatedDigits => write!(f, "PIN must not repeat one digit"),
+            PinError::SequentialDigits => write!(f, "PIN must not be a digit sequence"),
+        }
+    }
+}
+
+impl std::error::Error for PinError {}
+
+/// Check a candidate PIN against `policy`, for provisioning flows.
+pub fn validate_pin(pin: &[Key], policy: &PinPolicy) -> Result<(), PinError> {
+    let digits: Vec<u64> = pin.iter().filter_map(|key| key.digit()).collect();
+    if digits.len() != pin.len() {
+        return Err(PinError::NotADigit);
+    }
+    if digits.len() < policy.min_length {
+        return Err(PinError::TooShort {
+            minimum: policy.min_length,
+        });
+    }
+    if policy.forbid_repeated && digits.windows(2).all(|pair| pair[0] == pair[1]) {
+        return Err(PinError::RepeatedDigits);
+    }
+    if policy.forbid_sequential
+        && (digits.windows(2).all(|pair| pair[1] == pair[0] + 1)
+            || digits.windows(2).all(|pair| pair[0] == pair[1] + 1))
+    {
+        return Err(PinError::SequentialDigits);
+    }
+    Ok(())
+}
+
+/// An amount of cash, in the machine's minor units.
+///
+/// A dedicated type so amounts cannot be confused with the crate's
+/// other raw `u64`s — card numbers and PIN hashes mean very different
+/// things. Arithmetic is checked: overdrawing a `Money` answers `None`
+/// instead of wrapping.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Money(pub u64);
+
+impl Money {
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Option<Money> {
+        self.0.checked_sub(other.0).map(Money)
+    }
+
+    pub fn checked_mul(self, factor: u64) -> Option<Money> {
+        self.0.checked_mul(factor).map(Money)
+    }
+
+    /// Format with `scale` decimal places: at scale 2 the minor units
+    /// are cents and 150 renders as `$1.50`; at scale 0 they are whole
+    /// dollars.
+    pub fn display(self, scale: u32) -> String {
+        // A scale too large for u64 cannot place a decimal point; fall
+        // back to plain minor units rather than overflowing.
+        let Some(unit) = 10u64.checked_pow(scale) else {
+            return format!("${}", self.0);
+        };
+        if scale == 0 {
+            return format!("${}", self.0);
+        }
+        format!(
+            "${}.{:0width$}",
+            self.0 / unit,
+            self.0 % unit,
+            width = scale as usize
+        )
+    }
+}
+
+impl From<u64> for Money {
+    fn from(amount: u64) -> Self {
+        Money(amount)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "${}", self.0)
+    }
+}
+
+/// How a withdrawal fee interacts with denomination rounding.
+///
+/// The fee is withheld from the cash handed over (the account is always
+/// debited payout + fee), and the payout must be formable from bills, so
+/// the order of the two adjustments changes what comes out. A $17
+/// request with a $2 fee on a $5-bill machine pays out $15 under
+/// [`FeeOrder::FeeThenRound`] (round 17 − 2 = 15) but $10 under
+/// [`FeeOrder::RoundThenFee`] (round 17 to 15, take the fee, round 13
+/// down again).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FeeOrder {
+    /// Take the fee off the request, then round the remainder down to a
+    /// formable amount.
+    #[default]
+    FeeThenRound,
+    /// Round the request down first, take the fee from the rounded
+    /// amount, and round once more so the payout stays formable.
+    RoundThenFee,
+}
+
+/// What a successful authentication does beyond opening the session.
+///
+/// Once upon a time a correct PIN mysteriously cost the machine a
+/// dollar; this makes the policy explicit. The default charges nothing —
+/// authentication never moves physical cash either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthEffect {
+    /// A correct PIN just opens the session.
+    #[default]
+    None,
+    /// The institution books an inquiry fee against the card's account
+    /// (when one is registered). No physical cash moves.
+    ChargeFee(u64),
+}
+
+/// Why the machine silently dropped the last action.
+///
+/// The transition API stays infallible — ignored input simply returns
+/// the same state — but drivers debugging a script want to know *why*
+/// nothing happened. [`Atm::last_ignore_reason`] remembers the reason
+/// for the most recent no-op, cleared again by any accepted action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IgnoreReason {
+    /// The machine is unpowered; only the power coming back matters.
+    Unpowered,
+    /// Keypad input arrived before any card was swiped.
+    KeyBeforeSwipe,
+    /// The machine is locked and ignores customer input.
+    MachineLocked,
+    /// A note was fed in outside a session.
+    DepositWhileUnauthenticated,
+    /// A note in a denomination the machine does not accept bounced.
+    NoteNotAccepted,
+    /// The action needs the operator's key switch.
+    SupervisorRequired,
+    /// The action does not apply to the machine's current state.
+    WrongState,
+}
+
+/// The trace of the last ignored action.
+///
+/// Like the injected handles, it is diagnostic only: cloning a machine
+/// carries it along, but state comparison ignores it entirely, so a
+/// dropped action still counts as "nothing happened" to the equality
+/// and absorbing-state tooling.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct IgnoreTrace(Option<IgnoreReason>);
+
+impl PartialEq for IgnoreTrace {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for IgnoreTrace {}
+
+/// Which of the card's accounts a session's transactions move money in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AccountType {
+    /// The everyday account; every session starts here.
+    #[default]
+    Checking,
+    /// The savings account, reachable via [`Action::SelectAccount`].
+    Savings,
+}
+
+/// The cash pool a session draws from, decided by the card.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Currency {
+    /// The machine's home currency.
+    #[default]
+    Local,
+    /// The foreign pool, stocked as US dollars here.
+    Usd,
+}
+
+/// Where the machine is in its authentication lifecycle.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+enum Auth {
+    /// No card has been swiped yet.
+    Waiting,
+    /// A card carrying this expected PIN hash was swiped; PIN digits are
+    /// being entered.
+    Authenticating(u64),
+    /// The PIN matched; a withdrawal amount may be entered.
+    Authenticated,
+    /// Notes are being fed in for a deposit; each note is held until
+    /// `Enter` credits them or a cancel hands them back.
+    Depositing(Vec<u64>),
+    /// The last card was refused (hotlisted); the screen shows why until
+    /// another card is presented.
+    CardRejected,
+    /// Too many failed PIN attempts; the machine ignores all input.
+    Locked,
+}
+
+/// One completed customer transaction, as listed on a mini-statement,
+/// stamped with the machine clock (`at`, in the machine's seconds) when
+/// it settled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Transaction {
+    /// Cash left the machine.
+    Withdrawal { amount: u64, at: u64 },
+    /// Notes were fed in and credited.
+    Deposit { amount: u64, at: u64 },
+}
+
+/// An observable side effect requested by a transition.
+///
+/// The state machine itself stays pure; effects tell the surrounding
+/// hardware (dispenser, printer, screen) what to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect {
+    /// Cash was dispensed to the customer, as the given bills. The
+    /// receipt also shows the machine balance after the transaction.
+    Dispensed {
+        amount: u64,
+        bills: Vec<u64>,
+        balance_after: u64,
+    },
+    /// The bank network did not answer; the operation was cancelled.
+    NetworkError,
+    /// The requested amount was not formable from the denominations, so
+    /// the nearest lower formable amount was dispensed instead.
+    RoundedDown { requested: u64, dispensed: u64 },
+    /// A forgotten card sat in the reader too long and was swallowed.
+    CardRetained,
+    /// The presented card is hotlisted and was refused.
+    CardBlocked,
+    /// The presented card is past its expiry and was refused.
+    CardExpired,
+    /// The reader only caught part of the stripe; the customer must
+    /// swipe again to confirm.
+    ReswipeNeeded,
+    /// A finalized deposit was credited.
+    Deposited { amount: u64 },
+    /// A mini-statement was printed: the most recent transactions,
+    /// newest last.
+    Statement(Vec<Transaction>),
+    /// The same card was swiped suspiciously often in a short window and
+    /// was refused.
+    SuspiciousActivity,
+    /// Whether a card is sitting in the reader, in answer to
+    /// [`Action::CardStatus`].
+    CardPresent(bool),
+    /// The machine's identity, in answer to [`Action::Identify`], for
+    /// telling machines apart in shared logs.
+    MachineId(String),
+    /// The dispenser jammed mid-withdrawal: no cash came out and none
+    /// was debited.
+    DispenserJam,
+    /// The machine's remaining cash can no longer cover one maximum
+    /// withdrawal — a warning that rides along with a dispense via
+    /// [`Atm::next_state_with_effects`].
+    LowCash { remaining: u64 },
+    /// A PIN attempt failed. Zero attempts remaining means the machine
+    /// has just locked.
+    WrongPin { attempts_remaining: u8 },
+    /// A deposit was cancelled: hand these notes back, in the order they
+    /// were inserted. Nothing was credited.
+    ReturnNotes(Vec<u64>),
+    /// The last receipt came out of the printer again, with the text it
+    /// was printed with.
+    ReceiptReprinted(String),
+    /// Money moved between accounts: the session's selected account was
+    /// debited and the target credited. No cash moved.
+    Transferred { to: u64, amount: u64 },
+    /// A balance receipt was printed: the session card's account balance,
+    /// or `None` for cards the machine keeps no account for.
+    BalancePrinted { balance: Option<u64> },
+    /// The operator asked what the machine holds: the physical cash
+    /// total, with the per-denomination bill counts when an inventory is
+    /// kept (empty in the flat-cash model).
+    MachineCash {
+        total: u64,
+        inventory: HashMap<u64, u64>,
+    },
+}
+
+impl Effect {
+    /// The customer-facing message for this effect, in `language`.
+    ///
+    /// The small table here is the single source of UX strings; `Display`
+    /// is this in English.
+    pub fn message(&self, language: Language) -> String {
+        match (self, language) {
+            (
+                Effect::Dispensed {
+                    amount,
+                    balance_after,
+                    ..
+                },
+                Language::English,
+            ) => {
+                format!("Please take your ${amount}. New balance: ${balance_after}")
+            }
+            (
+                Effect::Dispensed {
+                    amount,
+                    balance_after,
+                    ..
+                },
+                Language::Spanish,
+            ) => {
+                format!("Por favor retire sus ${amount}. Nuevo saldo: ${balance_after}")
+            }
+            (Effect::NetworkError, Language::English) => {
+                "Network error, please try again later".to_string()
+            }
+            (Effect::NetworkError, Language::Spanish) => {
+                "Error de red, intente de nuevo más tarde".to_string()
+            }
+            (Effect::RoundedDown { requested, dispensed }, Language::English) => {
+                format!("${requested} is not available in our bills; dispensing ${dispensed}")
+            }
+            (Effect::RoundedDown { requested, dispensed }, Language::Spanish) => {
+                format!("${requested} no está disponible en nuestros billetes; entregando ${dispensed}")
+            }
+            (Effect::CardRetained, Language::English) => {
+                "Card retained; contact your bank".to_string()
+            }
+            (Effect::CardRetained, Language::Spanish) => {
+                "Tarjeta retenida; contacte a su banco".to_string()
+            }
+            (Effect::CardBlocked, Language::English) => {
+                "This card cannot be used; contact your bank".to_string()
+            }
+            (Effect::CardBlocked, Language::Spanish) => {
+                "Esta tarjeta no puede usarse; contacte a su banco".to_string()
+            }
+            (Effect::ReswipeNeeded, Language::English) => {
+                "Card not read; please swipe again".to_string()
+            }
+            (Effect::ReswipeNeeded, Language::Spanish) => {
+                "Tarjeta no leída; deslice de nuevo".to_string()
+            }
+            (Effect::CardExpired, Language::English) => {
+                "This card has expired; contact your bank".to_string()
+            }
+            (Effect::CardExpired, Language::Spanish) => {
+                "Esta tarjeta ha vencido; contacte a su banco".to_string()
+            }
+            (Effect::Deposited { amount }, Language::English) => {
+                format!("Deposited ${amount}")
+            }
+            (Effect::Deposited { amount }, Language::Spanish) => {
+                format!("Depositado ${amount}")
+            }
+            (Effect::Statement(entries), Language::English) => {
+                format!("Printed your last {} transactions", entries.len())
+            }
+            (Effect::Statement(entries), Language::Spanish) => {
+                format!("Imprimiendo sus últimas {} transacciones", entries.len())
+            }
+            (Effect::SuspiciousActivity, Language::English) => {
+                "Suspicious activity detected; card refused".to_string()
+            }
+            (Effect::SuspiciousActivity, Language::Spanish) => {
+                "Actividad sospechosa detectada; tarjeta rechazada".to_string()
+            }
+            // An identity is for logs, not customers; one form fits all.
+            (Effect::MachineId(id), _) => format!("Machine {id}"),
+            (Effect::CardPresent(true), Language::English) => "Card present".to_string(),
+            (Effect::CardPresent(false), Language::English) => {
+                "Please insert your card".to_string()
+            }
+            (Effect::CardPresent(true), Language::Spanish) => "Tarjeta presente".to_string(),
+            (Effect::CardPresent(false), Language::Spanish) => {
+                "Por favor inserte su tarjeta".to_string()
+            }
+            (Effect::WrongPin { attempts_remaining }, Language::English) => {
+                format!("Incorrect PIN; {attempts_remaining} attempts remaining")
+            }
+            (Effect::WrongPin { attempts_remaining }, Language::Spanish) => {
+                format!("PIN incorrecto; quedan {attempts_remaining} intentos")
+            }
+            // A reprint shows exactly what was printed the first time,
+            // whatever language that was in.
+            (Effect::ReceiptReprinted(text), _) => text.clone(),
+            (Effect::ReturnNotes(notes), Language::English) => {
+                format!("Deposit cancelled; please take back your {} notes", notes.len())
+            }
+            (Effect::ReturnNotes(notes), Language::Spanish) => {
+                format!("Depósito cancelado; retire sus {} billetes", notes.len())
+            }
+            (Effect::LowCash { remaining }, Language::English) => {
+                format!("Machine running low on cash: ${remaining} left")
+            }
+            (Effect::LowCash { remaining }, Language::Spanish) => {
+                format!("La máquina tiene poco efectivo: quedan ${remaining}")
+            }
+            (Effect::DispenserJam, Language::English) => {
+                "Dispenser jammed; no cash was taken from your account".to_string()
+            }
+            (Effect::DispenserJam, Language::Spanish) => {
+                "Dispensador atascado; no se debitó su cuenta".to_string()
+            }
+            (Effect::Transferred { amount, .. }, Language::English) => {
+                format!("Transferred ${amount}")
+            }
+            (Effect::Transferred { amount, .. }, Language::Spanish) => {
+                format!("Transferido ${amount}")
+            }
+            (Effect::BalancePrinted { balance }, Language::English) => match balance {
+                Some(balance) => format!("Your balance: ${balance}"),
+                None => "Balance not available for this card".to_string(),
+            },
+            (Effect::BalancePrinted { balance }, Language::Spanish) => match balance {
+                Some(balance) => format!("Su saldo: ${balance}"),
+                None => "Saldo no disponible para esta tarjeta".to_string(),
+            },
+            (Effect::MachineCash { total, .. }, Language::English) => {
+                format!("Machine holds ${total}")
+            }
+            (Effect::MachineCash { total, .. }, Language::Spanish) => {
+                format!("La máquina contiene ${total}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message(Language::English))
+    }
+}
+
+/// Ways an ATM operation can fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AtmError {
+    /// The machine does not hold enough physical cash for the request.
+    MachineOutOfCash,
+    /// The customer's account balance does not cover the request.
+    InsufficientAccountFunds,
+    /// The entered amount was empty, zero, or otherwise malformed.
+    InvalidAmount,
+    /// The machine's bill denominations cannot form `requested` exactly,
+    /// so the UI may want to suggest a nearby representable amount.
+    CannotMakeExactAmount { requested: u64 },
+    /// Dispensing would cut into the machine's configured cash reserve.
+    BelowReserve,
+}
+
+impl fmt::Display for AtmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AtmError::MachineOutOfCash => write!(f, "not enough cash in the machine"),
+            AtmError::InsufficientAccountFunds => {
+                write!(f, "insufficient funds in the account")
+            }
+            AtmError::InvalidAmount => write!(f, "invalid amount"),
+            AtmError::CannotMakeExactAmount { requested } => {
+                write!(f, "cannot make ${requested} exactly from available bills")
+            }
+            AtmError::BelowReserve => {
+                write!(f, "withdrawal would cut into the machine's cash reserve")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AtmError {}
+
+/// How the machine breaks a withdrawal into bills.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DispensePolicy {
+    /// Prefer the largest bills, minimising the number handed out.
+    #[default]
+    FewestBills,
+    /// Prefer the smallest bills, e.g. to drain excess small denominations.
+    SmallBillsFirst,
+}
+
+/// Aggregate counters of what the machine has seen, bumped as transitions
+/// happen. Distinct from any per-transaction history: these are totals an
+/// operator glances at, not an audit trail.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Sessions started by a card swipe or tap.
+    pub swipes: u64,
+    /// Keys pressed during a session (PIN and amount entry).
+    pub keypresses: u64,
+    /// Completed withdrawals, including rounded-down ones.
+    pub withdrawals: u64,
+    /// Failed PIN attempts plus refused withdrawals.
+    pub failures: u64,
+}
+
+/// A snapshot of every tunable on a machine, separate from its runtime
+/// state. Handy for diagnostics and for serializing machine setup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtmConfig {
+    /// Bill denominations the machine can dispense, largest first.
+    pub denominations: Vec<u64>,
+    /// How withdrawals are broken into bills.
+    pub dispense_policy: DispensePolicy,
+    /// Whether short-dispensing a non-formable request is allowed.
+    pub allow_partial: bool,
+    /// Whether non-formable requests are rounded down with a notice.
+    pub round_down: bool,
+    /// Failed PIN attempts tolerated before the machine locks.
+    pub max_attempts: u8,
+    /// Largest withdrawal allowed in a PIN-less contactless session.
+    pub tap_limit: u64,
+    /// Largest single withdrawal allowed.
+    pub max_withdrawal: u64,
+    /// Total a customer may withdraw in one day.
+    pub daily_limit: u64,
+    /// Seconds of inactivity before an in-progress session is abandoned.
+    pub idle_timeout: u64,
+    /// Decimal places accepted in entered amounts.
+    pub amount_scale: u32,
+}
+
+/// Aggregate statistics over a machine's transaction history, for
+/// operator dashboards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryStats {
+    /// Every dollar withdrawn across the history.
+    pub total_withdrawn: u64,
+    /// Every dollar deposited across the history.
+    pub total_deposited: u64,
+    /// Failed PIN attempts over the machine's lifetime.
+    pub failed_pins: u64,
+    /// Mean withdrawal size, rounded down; zero with no withdrawals.
+    pub average_withdrawal: u64,
+}
+
+/// A versioned capture of machine state for persistence.
+///
+/// The variant is the version tag: old serialized snapshots keep
+/// deserializing as their original variant, and
+/// [`Atm::from_snapshot`] migrates them forward. Version 1 predates
+/// most of the machine and carried only the cash and the lockout
+/// counter; version 2 snapshots the whole machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Snapshot {
+    V1 {
+        cash_inside: u64,
+        failed_attempts: u8,
+    },
+    V2 {
+        machine: Box<Atm>,
+    },
+}
+
+/// The machine state a frontend may see: no PIN hash, no keystrokes —
+/// just what the screen needs to render.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicAtmState {
+    /// Physical cash in the machine.
+    pub cash_inside: u64,
+    /// The authentication stage by name only; any payload (such as the
+    /// expected PIN hash) stays inside the machine.
+    pub auth_kind: String,
+    /// How many keys the customer has pressed since the last `Enter`,
+    /// for echoing `*`s — never the keys themselves.
+    pub pending_keys: usize,
+    /// The screen prompt for this state.
+    pub prompt: String,
+}
+
+/// The ATM itself: configuration plus current state.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Atm {
+    /// Physical cash in the machine, in dollars.
+    cash_inside: u64,
+    /// Physical cash in the foreign (USD) pool. Only sessions opened by
+    /// a card in `foreign_cards` draw on it.
+    usd_inside: u64,
+    /// Cards whose withdrawals debit the USD pool instead of the local
+    /// one.
+    foreign_cards: HashSet<u64>,
+    /// The authentication lifecycle, including the expected hash while a
+    /// PIN is being entered.
+    expected_pin_hash: Auth,
+    /// Keys pressed since the last `Enter`.
+    keystroke_register: Vec<Key>,
+    /// Bill denominations the machine can dispense.
+    denominations: Vec<u64>,
+    /// How withdrawals are broken into bills.
+    dispense_policy: DispensePolicy,
+    /// Whether a request that cannot be formed exactly from the bill
+    /// denominations may be satisfied by dispensing the largest formable
+    /// lower amount instead.
+    allow_partial: bool,
+    /// Like `allow_partial`, but the customer is told via
+    /// [`Effect::RoundedDown`] instead of being short-changed silently.
+    round_down: bool,
+    /// Consecutive failed PIN attempts since the last success.
+    failed_attempts: u8,
+    /// When the most recent failed attempt happened, for the advisory
+    /// backoff window.
+    last_failed_attempt_at: Option<u64>,
+    /// Failed PIN attempts over the machine's lifetime — unlike
+    /// `failed_attempts`, never reset by a success.
+    lifetime_failed_pins: u64,
+    /// Failed PIN attempts tolerated before the machine locks.
+    max_attempts: u8,
+    /// Whether the current session began with a contactless tap.
+    contactless: bool,
+    /// Whether a card is sitting in the reader (swiped and not yet taken
+    /// back; taps never insert a card).
+    card_inserted: bool,
+    /// Whether the current session runs on a dipped chip card, which
+    /// holds the session open until the card is removed.
+    chip_session: bool,
+    /// Ticks a returned card may sit in the reader before being swallowed.
+    card_timeout: u64,
+    /// Largest withdrawal allowed in a PIN-less contactless session.
+    tap_limit: u64,
+    /// Largest single withdrawal allowed.
+    max_withdrawal: u64,
+    /// An operator-approved per-withdrawal limit that overrides
+    /// `max_withdrawal` once, consumed by the next dispense.
+    elevated_limit: Option<u64>,
+    /// Per-card withdrawal ceilings for account tiers; cards without an
+    /// entry fall back to the machine-wide `max_withdrawal`.
+    limit_for_account: HashMap<u64, u64>,
+    /// Total a customer may withdraw in one day.
+    daily_limit: u64,
+    /// Amount withdrawn since the last `NewDay`.
+    withdrawn_today: u64,
+    /// Seconds since boot, advanced by `Action::Tick`.
+    now: u64,
+    /// Value of `now` at the last customer action, for the idle timeout.
+    last_activity: u64,
+    /// Seconds of inactivity before an in-progress session is abandoned.
+    idle_timeout: u64,
+    /// Decimal places in entered amounts: 0 means whole-dollar entry,
+    /// 2 means `cash_inside` and amounts are in cents.
+    amount_scale: u32,
+    /// What a correct PIN does beyond opening the session.
+    on_auth: AuthEffect,
+    /// Fee withheld from each withdrawal's payout. Zero means free.
+    withdrawal_fee: u64,
+    /// How the fee and denomination rounding interleave.
+    fee_order: FeeOrder,
+    /// Injected business rules, all of which must pass before any
+    /// dispense. Not persisted: a deserialized machine starts rule-free.
+    #[serde(skip)]
+    rules: Vec<RuleHandle>,
+    /// A shared ledger that overrides the local account maps, for
+    /// fleets of machines drawing on one balance. Not persisted.
+    #[serde(skip)]
+    ledger: Option<LedgerHandle>,
+    /// Where [`Atm::sync_clock`] reads the time from. Not persisted: a
+    /// deserialized machine falls back to the system clock.
+    #[serde(skip)]
+    clock: ClockHandle,
+    /// Which digit key sits at each screen position, for position-based
+    /// input. Identity unless the machine scrambles it per session.
+    keypad_layout: [Key; 10],
+    /// When set, each swipe reshuffles `keypad_layout` with this seed,
+    /// then advances it — the security-demo scramble mode.
+    shuffle_seed: Option<u64>,
+    /// Which digit each key enters, for rewired or alphanumeric keypads.
+    /// Keys absent from the map enter no digit. PIN and amount entry
+    /// both read keys through it.
+    digit_map: HashMap<Key, u8>,
+    /// Language the screen currently speaks.
+    language: Language,
+    /// How PIN keystrokes are hashed for comparison with the card's hash.
+    /// Not persisted: a deserialized machine falls back to the default.
+    #[serde(skip)]
+    pin_hasher: HasherHandle,
+    /// Completed transactions since the counters were last reset.
+    transaction_count: u64,
+    /// Completed transactions in order, newest last, for mini-statements.
+    history: Vec<Transaction>,
+    /// Aggregate transition counters.
+    metrics: Metrics,
+    /// Whether the operator's maintenance key switch is on, unlocking
+    /// supervisor operations.
+    maintenance_mode: bool,
+    /// Whether mains power is on. An unpowered machine ignores everything
+    /// except the power coming back.
+    powered: bool,
+    /// Hotlisted card numbers. In this model the swipe payload doubles as
+    /// the card's identity.
+    blocked_cards: HashSet<u64>,
+    /// Bills on hand by denomination. Empty means the flat-cash model:
+    /// `cash_inside` is authoritative and bills are unlimited.
+    inventory: HashMap<u64, u64>,
+    /// Known checking balances by card. Cards without an entry are not
+    /// balance-checked (the machine only guards its own cash for them).
+    accounts: HashMap<u64, u64>,
+    /// Known savings balances by card, used when the session selects
+    /// [`AccountType::Savings`].
+    savings_accounts: HashMap<u64, u64>,
+    /// Which of the card's accounts this session's transactions move
+    /// money in. Reset to checking at each session open.
+    selected_account: AccountType,
+    /// This machine's serial/identity, for multi-machine logs.
+    id: String,
+    /// Card that opened the current (or most recent) session.
+    current_card: Option<u64>,
+    /// Whether a successful withdrawal ends the session. When false the
+    /// customer stays authenticated for further transactions.
+    single_transaction: bool,
+    /// Demo/training mode: transitions and effects run as usual, but the
+    /// physical cash (both pools and the bill inventory) never moves.
+    training: bool,
+    /// Why the most recent action was silently dropped, if it was;
+    /// cleared by any accepted action.
+    last_ignore_reason: IgnoreTrace,
+    /// A key whose contact is stuck closed, registering alongside every
+    /// keypress, for robustness testing.
+    stuck_key: Option<Key>,
+    /// Whether the dispenser mechanism is jammed. Withdrawals fail
+    /// without debiting until an operator clears it.
+    jammed: bool,
+    /// Whether this session's transactions print receipts. Reset to
+    /// printing at each session open.
+    print_receipts: bool,
+    /// The last transaction's printed receipt, kept briefly so it can be
+    /// reprinted, with the time it was printed.
+    last_receipt: Option<String>,
+    /// When `last_receipt` came out of the printer.
+    last_receipt_at: u64,
+    /// Every dollar ever dispensed by this machine, for reconciliation.
+    /// Monotonic: neither `NewDay` nor a counters reset touches it.
+    lifetime_dispensed: u64,
+    /// A float the machine refuses to dispense below, so it is never
+    /// drained to the last dollar. Zero means no reserve.
+    min_reserve: u64,
+    /// Cash reserved by a pre-authorization hold: still physically in
+    /// the machine, but not available to withdrawals until captured or
+    /// released.
+    held_amount: u64,
+    /// A partial card read awaiting its confirming re-swipe: the card
+    /// and when the first partial read happened.
+    pending_swipe: Option<(u64, u64)>,
+    /// Card and time of each swipe inside the rapid-swipe window, for
+    /// the fraud detector. A completed PIN attempt clears the card's
+    /// entries: the signal is swiping over and over without ever keying
+    /// a PIN, not a customer retrying their code.
+    recent_swipes: Vec<(u64, u64)>,
+}
+
+impl Atm {
+    /// Denominations a freshly constructed machine dispenses.
+    pub const DEFAULT_DENOMINATIONS: &'static [u64] = &[20, 10, 5, 1];
+    /// Largest single withdrawal on a freshly constructed machine.
+    pub const DEFAULT_MAX_WITHDRAWAL: u64 = 500;
+    /// Default per-day withdrawal allowance.
+    pub const DEFAULT_DAILY_LIMIT: u64 = 2_000;
+    /// Default idle timeout, in seconds.
+    pub const DEFAULT_IDLE_TIMEOUT: u64 = 30;
+    /// Failed PIN attempts tolerated by default before locking.
+    pub const DEFAULT_MAX_ATTEMPTS: u8 = 3;
+    /// Default cap on PIN-less contactless withdrawals.
+    pub const DEFAULT_TAP_LIMIT: u64 = 50;
+    /// Default seconds before a forgotten card is swallowed.
+    pub const DEFAULT_CARD_TIMEOUT: u64 = 10;
+    /// Seconds within which repeated swipes of one card count as rapid.
+    pub const DEFAULT_SWIPE_WINDOW: u64 = 10;
+    /// Swipes of one card tolerated inside the swipe window; one more is
+    /// treated as a fraud signal.
+    pub const DEFAULT_MAX_RAPID_SWIPES: usize = 3;
+    /// Seconds after a transaction during which its receipt can be
+    /// reprinted.
+    pub const DEFAULT_RECEIPT_WINDOW: u64 = 30;
+    /// Seconds a partial card read waits for its confirming re-swipe.
+    pub const DEFAULT_RESWIPE_WINDOW: u64 = 10;
+    /// Seconds of advisory cooldown per accumulated failed PIN attempt.
+    pub const DEFAULT_ATTEMPT_BACKOFF: u64 = 5;
+
+    /// A machine holding `cash_inside` dollars, waiting for a card.
+    pub fn new(cash_inside: u64) -> Self {
+        Atm {
+            cash_inside,
+            usd_inside: 0,
+            foreign_cards: HashSet::new(),
+            expected_pin_hash: Auth::Waiting,
+            keystroke_register: Vec::new(),
+            denominations: Self::DEFAULT_DENOMINATIONS.to_vec(),
+            dispense_policy: DispensePolicy::default(),
+            allow_partial: false,
+            round_down: false,
+            failed_attempts: 0,
+            last_failed_attempt_at: None,
+            lifetime_failed_pins: 0,
+            max_attempts: Self::DEFAULT_MAX_ATTEMPTS,
+            contactless: false,
+            card_inserted: false,
+            chip_session: false,
+            card_timeout: Self::DEFAULT_CARD_TIMEOUT,
+            tap_limit: Self::DEFAULT_TAP_LIMIT,
+            max_withdrawal: Self::DEFAULT_MAX_WITHDRAWAL,
+            elevated_limit: None,
+            limit_for_account: HashMap::new(),
+            daily_limit: Self::DEFAULT_DAILY_LIMIT,
+            withdrawn_today: 0,
+            now: 0,
+            last_activity: 0,
+            idle_timeout: Self::DEFAULT_IDLE_TIMEOUT,
+            amount_scale: 0,
+            on_auth: AuthEffect::default(),
+            withdrawal_fee: 0,
+            fee_order: FeeOrder::default(),
+            rules: Vec::new(),
+            ledger: None,
+            clock: ClockHandle::default(),
+            keypad_layout: Key::digit_row(),
+            shuffle_seed: None,
+            digit_map: Key::standard_digit_map(),
+            language: Language::default(),
+            pin_hasher: HasherHandle::default(),
+            transaction_count: 0,
+            history: Vec::new(),
+            metrics: Metrics::default(),
+            maintenance_mode: false,
+            powered: true,
+            blocked_cards: HashSet::new(),
+            inventory: HashMap::new(),
+            accounts: HashMap::new(),
+            savings_accounts: HashMap::new(),
+            selected_account: AccountType::default(),
+            id: String::new(),
+            current_card: None,
+            single_transaction: true,
+            training: false,
+            last_ignore_reason: IgnoreTrace::default(),
+            stuck_key: None,
+            jammed: false,
+            print_receipts: true,
+            last_receipt: None,
+            last_receipt_at: 0,
+            held_amount: 0,
+            min_reserve: 0,
+            lifetime_dispensed: 0,
+            pending_swipe: None,
+            recent_swipes: Vec::new(),
+        }
+    }
+
+    /// A machine stocked with an explicit bill inventory (denomination →
+    /// count). The cash total and the dispensable denominations are
+    /// computed from the bills, bridging the flat-cash and bill-inventory
+    /// designs.
+    pub fn with_inventory(inventory: HashMap<u64, u64>) -> Self {
+        let cash_inside = inventory
+            .iter()
+            .fold(0u64, |total, (denomination, count)| {
+                total.saturating_add(denomination.saturating_mul(*count))
+            });
+        let atm = Atm::new(cash_inside)
+            .with_denominations(inventory.keys().copied().collect());
+        Atm { inventory, ..atm }
+    }
+
+    /// Register (or overwrite) the account balance behind `card`, making
+    /// its withdrawals balance-checked as well as cash-checked.
+    pub fn with_account(mut self, card: u64, balance: u64) -> Self {
+        self.accounts.insert(card, balance);
+        self
+    }
+
+    /// The balance of the account behind `card`, if one is registered.
+    pub fn account_balance(&self, card: u64) -> Option<u64> {
+        self.accounts.get(&card).copied()
+    }
+
+    /// Give `card` its own per-transaction withdrawal ceiling — account
+    /// tiers in miniature. Cards without one use the machine-wide
+    /// maximum.
+    pub fn with_account_limit(mut self, card: u64, limit: u64) -> Self {
+        self.limit_for_account.insert(card, limit);
+        self
+    }
+
+    /// The per-transaction ceiling for the session card: the operator's
+    /// one-time approval beats the card's tier limit, which beats the
+    /// machine default.
+    fn per_withdrawal_limit(&self) -> u64 {
+        self.elevated_limit.unwrap_or_else(|| {
+            self.current_card
+                .and_then(|card| self.limit_for_account.get(&card).copied())
+                .unwrap_or(self.max_withdrawal)
+        })
+    }
+
+    /// Register (or overwrite) the savings balance behind `card`, for
+    /// sessions that select [`AccountType::Savings`].
+    pub fn with_savings_account(mut self, card: u64, balance: u64) -> Self {
+        self.savings_accounts.insert(card, balance);
+        self
+    }
+
+    /// The savings balance behind `card`, if one is registered.
+    pub fn savings_balance(&self, card: u64) -> Option<u64> {
+        self.savings_accounts.get(&card).copied()
+    }
+
+    /// The checking and savings maps with the session card's selected
+    /// balance passed through `apply` — the one place transactions
+    /// adjust whichever account the session picked.
+    fn with_selected_balance(
+        &self,
+        apply: impl Fn(u64) -> u64,
+    ) -> (HashMap<u64, u64>, HashMap<u64, u64>) {
+        let mut checking = self.accounts.clone();
+        let mut savings = self.savings_accounts.clone();
+        let map = match self.selected_account {
+            AccountType::Checking => &mut checking,
+            AccountType::Savings => &mut savings,
+        };
+        if let Some(balance) = self.current_card.and_then(|card| map.get_mut(&card)) {
+            *balance = apply(*balance);
+        }
+        (checking, savings)
+    }
+
+    /// Settle a debit or credit of `amount` against the session card's
+    /// balance, wherever it lives: the shared ledger when one is
+    /// injected, otherwise the selected local account map. Returns the
+    /// local maps for the successor state either way. Training mode
+    /// settles nothing.
+    fn settle_account(&self, debit: bool, amount: u64) -> (HashMap<u64, u64>, HashMap<u64, u64>) {
+        if self.training {
+            return (self.accounts.clone(), self.savings_accounts.clone());
+        }
+        if let (Some(ledger), Some(card)) = (&self.ledger, self.current_card) {
+            if debit {
+                ledger.0.debit(card, amount);
+            } else {
+                ledger.0.credit(card, amount);
+            }
+            return (self.accounts.clone(), self.savings_accounts.clone());
+        }
+        self.with_selected_balance(|balance| {
+            if debit {
+                balance.saturating_sub(amount)
+            } else {
+                balance + amount
+            }
+        })
+    }
+
+    /// Stock the foreign (USD) pool with `cash`.
+    pub fn with_usd_cash(mut self, cash: u64) -> Self {
+        self.usd_inside = cash;
+        self
+    }
+
+    /// Mark `card` as foreign: its withdrawals debit the USD pool.
+    pub fn with_foreign_card(mut self, card: u64) -> Self {
+        self.foreign_cards.insert(card);
+        self
+    }
+
+    /// Physical cash currently in the foreign (USD) pool.
+    pub fn usd_inside(&self) -> u64 {
+        self.usd_inside
+    }
+
+    /// The pool the current (or most recent) session draws from.
+    pub fn session_currency(&self) -> Currency {
+        match self.current_card {
+            Some(card) if self.foreign_cards.contains(&card) => Currency::Usd,
+            _ => Currency::Local,
+        }
+    }
+
+    /// Start the machine with the maintenance key switch already on,
+    /// e.g. for operator test rigs.
+    pub fn with_maintenance_mode(mut self, on: bool) -> Self {
+        self.maintenance_mode = on;
+        self
+    }
+
+    /// Replace the PIN hasher. Cards must carry hashes produced by the
+    /// same hasher for authentication to succeed.
+    pub fn with_pin_hasher(mut self, hasher: impl PinHasher + Send + Sync + 'static) -> Self {
+        self.pin_hasher = HasherHandle(Arc::new(hasher));
+        self
+    }
+
+    /// Replace the dispensable denominations. Order does not matter; the
+    /// machine sorts them itself.
+    pub fn with_denominations(mut self, denominations: Vec<u64>) -> Self {
+        self.denominations = denominations;
+        self.denominations.sort_unstable_by(|a, b| b.cmp(a));
+        self
+    }
+
+    /// Choose how withdrawals are broken into bills.
+    pub fn with_dispense_policy(mut self, policy: DispensePolicy) -> Self {
+        self.dispense_policy = policy;
+        self
+    }
+
+    /// Allow dispensing the largest formable lower amount when the request
+    /// is not exactly representable.
+    pub fn with_allow_partial(mut self, allow: bool) -> Self {
+        self.allow_partial = allow;
+        self
+    }
+
+    /// Round unformable requests down to the nearest formable amount,
+    /// with an [`Effect::RoundedDown`] notice to the customer.
+    pub fn with_round_down(mut self, round: bool) -> Self {
+        self.round_down = round;
+        self
+    }
+
+    /// Cap PIN-less contactless withdrawals at `limit`.
+    pub fn with_tap_limit(mut self, limit: u64) -> Self {
+        self.tap_limit = limit;
+        self
+    }
+
+    /// Lock the machine after `attempts` consecutive failed PINs.
+    pub fn with_max_attempts(mut self, attempts: u8) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// Cap single withdrawals at `max`.
+    pub fn with_max_withdrawal(mut self, max: u64) -> Self {
+        self.max_withdrawal = max;
+        self
+    }
+
+    /// Cap total withdrawals per day at `limit`.
+    pub fn with_daily_limit(mut self, limit: u64) -> Self {
+        self.daily_limit = limit;
+        self
+    }
+
+    /// Abandon in-progress sessions after `seconds` of inactivity.
+    pub fn with_idle_timeout(mut self, seconds: u64) -> Self {
+        self.idle_timeout = seconds;
+        self
+    }
+
+    /// Accept `scale` decimal places in entered amounts (2 for cents).
+    /// All cash quantities are then in the corresponding minor unit.
+    pub fn with_amount_scale(mut self, scale: u32) -> Self {
+        self.amount_scale = scale;
+        self
+    }
+
+    /// Withhold `fee` from every withdrawal's payout (the account is
+    /// debited payout + fee). See [`FeeOrder`] for how the fee meets
+    /// denomination rounding.
+    pub fn with_withdrawal_fee(mut self, fee: u64) -> Self {
+        self.withdrawal_fee = fee;
+        self
+    }
+
+    /// Choose how the withdrawal fee and denomination rounding
+    /// interleave.
+    pub fn with_fee_order(mut self, order: FeeOrder) -> Self {
+        self.fee_order = order;
+        self
+    }
+
+    /// Give the machine a serial/identity for multi-machine logs.
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    /// This machine's serial/identity.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Keep a float of `reserve` dollars the machine will not dispense
+    /// below, rather than letting the last withdrawal empty it.
+    pub fn with_min_reserve(mut self, reserve: u64) -> Self {
+        self.min_reserve = reserve;
+        self
+    }
+
+    /// Choose whether a successful withdrawal logs the customer out
+    /// (the default) or leaves the session open for more transactions.
+    pub fn with_single_transaction(mut self, single: bool) -> Self {
+        self.single_transaction = single;
+        self
+    }
+
+    /// Inject the time source [`Atm::sync_clock`] reads, e.g. a
+    /// [`ManualClock`] in tests.
+    pub fn with_clock(mut self, clock: impl Clock + Send + Sync + 'static) -> Self {
+        self.clock = ClockHandle(Arc::new(clock));
+        self
+    }
+
+    /// Read the injected clock and set the machine's time to it — sugar
+    /// for [`Action::SetClock`], so drivers fire timeouts with one call.
+    pub fn sync_clock(&self) -> (Atm, Option<Effect>) {
+        Atm::transition(self, &Action::SetClock(self.clock.0.now()))
+    }
+
+    /// Draw this machine's account balances from a shared ledger
+    /// instead of its own maps, chaining any number of machines to one
+    /// central balance.
+    ///
+    /// This waives the pure-transition guarantee for account money:
+    /// applying a transition settles against the ledger immediately, so
+    /// tools that explore transitions speculatively — [`Atm::preview`],
+    /// [`states_equivalent`] — will move real balances on such machines.
+    pub fn with_ledger(mut self, ledger: impl BalanceProvider + Send + Sync + 'static) -> Self {
+        self.ledger = Some(LedgerHandle(Arc::new(ledger)));
+        self
+    }
+
+    /// Register a business rule that every withdrawal must pass, e.g.
+    /// a regional cap or a round-amounts-only policy. Rules stack: each
+    /// call adds one more.
+    pub fn with_rule(mut self, rule: impl WithdrawalRule + Send + Sync + 'static) -> Self {
+        self.rules.push(RuleHandle(Arc::new(rule)));
+        self
+    }
+
+    /// Run every registered rule against a withdrawal of `amount`,
+    /// reporting the first refusal.
+    fn check_rules(&self, amount: u64) -> Result<(), AtmError> {
+        self.rules.iter().try_for_each(|rule| rule.0.check(amount, self))
+    }
+
+    /// Choose what a correct PIN does beyond opening the session, e.g.
+    /// booking an inquiry fee against the account.
+    pub fn with_on_auth(mut self, on_auth: AuthEffect) -> Self {
+        self.on_auth = on_auth;
+        self
+    }
+
+    /// Scramble the keypad layout afresh on every swipe, deterministically
+    /// from `seed` — the shoulder-surfing demo: watching positions tells
+    /// an observer nothing about the digits.
+    pub fn with_keypad_shuffle(mut self, seed: u64) -> Self {
+        self.shuffle_seed = Some(seed);
+        self
+    }
+
+    /// Which digit key sits at each screen position this session, for
+    /// UIs drawing the (possibly scrambled) pad.
+    pub fn keypad_layout(&self) -> &[Key; 10] {
+        &self.keypad_layout
+    }
+
+    /// Rewire which digit each key enters, e.g. for alphanumeric pads.
+    /// Seed a custom layout from [`Key::standard_digit_map`]; keys left
+    /// out of the map enter no digit at all.
+    pub fn with_digit_map(mut self, digit_map: HashMap<Key, u8>) -> Self {
+        self.digit_map = digit_map;
+        self
+    }
+
+    /// Physical cash currently in the machine.
+    pub fn cash_inside(&self) -> u64 {
+        self.cash_inside
+    }
+
+    /// Total cash computed from the bills themselves: `value × count`
+    /// summed across the inventory, with checked arithmetic saturating
+    /// rather than wrapping. In the inventory model this is the source
+    /// of truth for dispensable cash; a flat-cash machine (no inventory)
+    /// answers its scalar `cash_inside`. Deposited notes go to the
+    /// vault, not the inventory, so after a deposit the scalar runs
+    /// ahead of this figure — by exactly the vault's contents.
+    pub fn total_cash(&self) -> u64 {
+        if self.inventory.is_empty() {
+            return self.cash_inside;
+        }
+        self.inventory
+            .iter()
+            .fold(0u64, |total, (denomination, count)| {
+                denomination
+                    .checked_mul(*count)
+                    .and_then(|value| total.checked_add(value))
+                    .unwrap_or(u64::MAX)
+            })
+    }
+
+    /// Every dollar this machine has ever dispensed. Monotonic, for
+    /// operator reconciliation; surviving `NewDay` and counter resets.
+    pub fn lifetime_dispensed(&self) -> u64 {
+        self.lifetime_dispensed
+    }
+
+    /// Physical cash currently in the machine, as typed [`Money`].
+    /// Render it with the machine's own scale via
+    /// [`Money::display`](Money::display).
+    pub fn cash(&self) -> Money {
+        Money(self.cash_inside)
+    }
+
+    /// Language the screen currently speaks.
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// `effect`'s message in the machine's current language.
+    pub fn message(&self, effect: &Effect) -> String {
+        effect.message(self.language)
+    }
+
+    /// Completed transactions since the counters were last reset.
+    pub fn transaction_count(&self) -> u64 {
+        self.transaction_count
+    }
+
+    /// Aggregate transition counters.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Whether supervisor operations are currently unlocked.
+    pub fn is_supervisor(&self) -> bool {
+        self.maintenance_mode
+    }
+
+    /// Why the most recent action was silently dropped, or `None` when
+    /// the last action was accepted.
+    pub fn last_ignore_reason(&self) -> Option<IgnoreReason> {
+        self.last_ignore_reason.0
+    }
+
+    /// Whether a PIN attempt would be worth making right now: false
+    /// while the machine is locked, or inside the advisory backoff
+    /// window after a failed attempt (5 seconds per accumulated
+    /// failure). The machine does not enforce the backoff — it is a
+    /// signal for UIs to gray out the keypad.
+    pub fn can_attempt_pin(&self) -> bool {
+        if self.expected_pin_hash == Auth::Locked {
+            return false;
+        }
+        match self.last_failed_attempt_at {
+            Some(at) => {
+                let backoff = Self::DEFAULT_ATTEMPT_BACKOFF * u64::from(self.failed_attempts);
+                self.now.saturating_sub(at) >= backoff
+            }
+            None => true,
+        }
+    }
+
+    /// Seconds of inactivity left before the session is abandoned, for
+    /// UIs counting down to auto-logout. `None` outside an authenticated
+    /// session, where there is nothing to log out of.
+    pub fn seconds_until_logout(&self) -> Option<u64> {
+        match self.expected_pin_hash {
+            Auth::Authenticated => {
+                let idle = self.now.saturating_sub(self.last_activity);
+                Some(self.idle_timeout.saturating_sub(idle))
+            }
+            _ => None,
+        }
+    }
+
+    /// The in-progress PIN attempt, for UIs that echo one `*` per digit.
+    /// Only a PIN entry qualifies: while authenticated the register holds
+    /// a withdrawal amount, which is not an attempt and answers `None`.
+    pub fn current_attempt(&self) -> Option<&[Key]> {
+        match self.expected_pin_hash {
+            Auth::Authenticating(_) => Some(&self.keystroke_register),
+            _ => None,
+        }
+    }
+
+    /// The PIN hash the machine is currently checking attempts against:
+    /// the swiped card's hash while a PIN is being entered, or the
+    /// session card's once authenticated. `None` outside a session,
+    /// where there is no expectation to verify against.
+    pub fn expected_pin_hash(&self) -> Option<u64> {
+        match self.expected_pin_hash {
+            Auth::Authenticating(hash) => Some(hash),
+            Auth::Authenticated => self.current_card,
+            _ => None,
+        }
+    }
+
+    /// Whether both machines are in the same kind of authentication
+    /// state, ignoring any payload (such as the expected PIN hash while
+    /// authenticating). Lets tests assert "still entering a PIN" without
+    /// hardcoding hash values.
+    pub fn same_auth_kind(&self, other: &Atm) -> bool {
+        std::mem::discriminant(&self.expected_pin_hash)
+            == std::mem::discriminant(&other.expected_pin_hash)
+    }
+
+    /// How many positions of `entered` differ from the PIN behind the
+    /// current card — a Hamming distance, recovered by brute-forcing the
+    /// card's hash with the machine's own hasher.
+    ///
+    /// Educational only: it exists to show why a short keypad PIN behind
+    /// an unsalted hash is no secret at all, and it must never exist in a
+    /// production machine — hence the `debug-insights` feature gate.
+    /// Answers `None` when no card is in session, when no sequence of
+    /// `entered`'s length matches the hash, or when `entered` is too long
+    /// to search exhaustively.
+    #[cfg(feature = "debug-insights")]
+    pub fn pin_distance(&self, entered: &[Key]) -> Option<usize> {
+        // Beyond a few keys the 12^n search space stops being classroom
+        // material.
+        const MAX_SEARCH_LEN: usize = 6;
+        let expected = self.current_card?;
+        if entered.len() > MAX_SEARCH_LEN {
+            return None;
+        }
+        let keys = Key::all();
+        // Walk every same-length key sequence like an odometer, keeping
+        // the smallest distance over all hash preimages.
+        let mut indices = vec![0usize; entered.len()];
+        let mut best: Option<usize> = None;
+        loop {
+            let candidate: Vec<Key> = indices.iter().map(|&i| keys[i]).collect();
+            if self.pin_hasher.0.hash(&candidate) == expected {
+                let distance = candidate
+                    .iter()
+                    .zip(entered)
+                    .filter(|(a, b)| a != b)
+                    .count();
+                best = Some(best.map_or(distance, |b| b.min(distance)));
+            }
+            let mut pos = 0;
+            loop {
+                if pos == indices.len() {
+                    return best;
+                }
+                indices[pos] += 1;
+                if indices[pos] < keys.len() {
+                    break;
+                }
+                indices[pos] = 0;
+                pos += 1;
+            }
+        }
+    }
+
+    /// Whether the machine could satisfy any withdrawal at all right
+    /// now: the dispenser is not jammed, and at least one denomination
+    /// is both in stock and covered by the uncommitted cash. UIs show
+    /// "temporarily unable to dispense cash" when this is false.
+    pub fn is_dispensable(&self) -> bool {
+        if self.jammed {
+            return false;
+        }
+        let available = self
+            .cash_inside
+            .saturating_sub(self.held_amount)
+            .saturating_sub(self.min_reserve);
+        self.denominations.iter().any(|&denomination| {
+            denomination > 0
+                && denomination <= available
+                && (self.inventory.is_empty()
+                    || self.inventory.get(&denomination).copied().unwrap_or(0) > 0)
+        })
+    }
+
+    /// Capture this machine as the current snapshot version.
+    pub fn to_snapshot(&self) -> Snapshot {
+        Snapshot::V2 {
+            machine: Box::new(self.clone()),
+        }
+    }
+
+    /// Rebuild a machine from any snapshot version, migrating old ones
+    /// forward: a v1 snapshot becomes a fresh machine with its cash and
+    /// lockout counter restored (locking it if the counter had already
+    /// hit the default limit).
+    pub fn from_snapshot(snapshot: Snapshot) -> Atm {
+        match snapshot {
+            Snapshot::V1 {
+                cash_inside,
+                failed_attempts,
+            } => {
+                let mut atm = Atm::new(cash_inside);
+                atm.failed_attempts = failed_attempts;
+                if failed_attempts >= atm.max_attempts {
+                    atm.expected_pin_hash = Auth::Locked;
+                }
+                atm
+            }
+            Snapshot::V2 { machine } => *machine,
+        }
+    }
+
+    /// The state a frontend may safely render, with the PIN hash and
+    /// the raw keystrokes stripped.
+    pub fn public_view(&self) -> PublicAtmState {
+        let auth_kind = match self.expected_pin_hash {
+            Auth::Waiting => "waiting",
+            Auth::Authenticating(_) => "authenticating",
+            Auth::Authenticated => "authenticated",
+            Auth::Depositing(_) => "depositing",
+            Auth::CardRejected => "card-rejected",
+            Auth::Locked => "locked",
+        };
+        PublicAtmState {
+            cash_inside: self.cash_inside,
+            auth_kind: auth_kind.to_string(),
+            pending_keys: self.keystroke_register.len(),
+            prompt: self.prompt().to_string(),
+        }
+    }
+
+    /// Aggregate statistics over the transaction history: the totals
+    /// moved in each direction, the lifetime failed-PIN count (tracked
+    /// separately, since the history only sees completed transactions),
+    /// and the mean withdrawal.
+    pub fn stats(&self) -> HistoryStats {
+        let mut total_withdrawn = 0u64;
+        let mut total_deposited = 0u64;
+        let mut withdrawals = 0u64;
+        for entry in &self.history {
+            match entry {
+                Transaction::Withdrawal { amount, .. } => {
+                    total_withdrawn += amount;
+                    withdrawals += 1;
+                }
+                Transaction::Deposit { amount, .. } => total_deposited += amount,
+            }
+        }
+        HistoryStats {
+            total_withdrawn,
+            total_deposited,
+            failed_pins: self.lifetime_failed_pins,
+            average_withdrawal: total_withdrawn.checked_div(withdrawals).unwrap_or(0),
+        }
+    }
+
+    /// The transaction log as CSV, for operator exports.
+    ///
+    /// Columns are `type,amount,timestamp`, newest row last; the
+    /// timestamp is the machine clock (epoch or boot seconds, however
+    /// the driver feeds it) when the transaction settled.
+    pub fn history_csv(&self) -> String {
+        let mut csv = String::from("type,amount,timestamp\n");
+        for entry in &self.history {
+            let (kind, amount, at) = match entry {
+                Transaction::Withdrawal { amount, at } => ("withdrawal", amount, at),
+                Transaction::Deposit { amount, at } => ("deposit", amount, at),
+            };
+            csv.push_str(&format!("{kind},{amount},{at}\n"));
+        }
+        csv
+    }
+
+    /// A 64-bit digest of the machine's dynamic state, so reachability
+    /// searches and model checkers can dedup states in a `HashSet<u64>`
+    /// instead of storing whole machines.
+    ///
+    /// Collision caveats: distinct states can share a fingerprint — it
+    /// is 64 bits, and configuration plus the bookkeeping collections
+    /// (accounts, inventory, hotlist, swipe log, history) are left out
+    /// deliberately, since they rarely distinguish states a search cares
+    /// about. Equal fingerprints are a strong hint, not proof; compare
+    /// the states themselves when it matters.
+    pub fn state_fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.cash_inside.hash(&mut hasher);
+        self.usd_inside.hash(&mut hasher);
+        self.held_amount.hash(&mut hasher);
+        self.expected_pin_hash.hash(&mut hasher);
+        self.keystroke_register.hash(&mut hasher);
+        self.failed_attempts.hash(&mut hasher);
+        self.withdrawn_today.hash(&mut hasher);
+        self.transaction_count.hash(&mut hasher);
+        self.now.hash(&mut hasher);
+        self.last_activity.hash(&mut hasher);
+        self.current_card.hash(&mut hasher);
+        (
+            self.contactless,
+            self.card_inserted,
+            self.maintenance_mode,
+            self.powered,
+            self.jammed,
+        )
+            .hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The machine's state squeezed into one byte, for embedded status
+    /// lines and telemetry:
+    ///
+    /// ```text
+    /// bits 0-2  auth state: 0 waiting, 1 authenticating, 2 authenticated,
+    ///           3 depositing, 4 card rejected, 5 locked
+    /// bit  3    out of service (unpowered or in maintenance)
+    /// bit  4    low cash (cannot cover one maximum withdrawal)
+    /// bit  5    locked, duplicated as a flag for easy masking
+    /// bits 6-7  reserved, always zero
+    /// ```
+    pub fn status_code(&self) -> u8 {
+        let mut code = match self.expected_pin_hash {
+            Auth::Waiting => 0,
+            Auth::Authenticating(_) => 1,
+            Auth::Authenticated => 2,
+            Auth::Depositing(_) => 3,
+            Auth::CardRejected => 4,
+            Auth::Locked => 5,
+        };
+        if !self.powered || self.maintenance_mode {
+            code |= 1 << 3;
+        }
+        if self.cash_inside < self.max_withdrawal {
+            code |= 1 << 4;
+        }
+        if self.expected_pin_hash == Auth::Locked {
+            code |= 1 << 5;
+        }
+        code
+    }
+
+    /// A snapshot of every tunable, as an [`AtmConfig`].
+    pub fn config(&self) -> AtmConfig {
+        AtmConfig {
+            denominations: self.denominations.clone(),
+            dispense_policy: self.dispense_policy,
+            allow_partial: self.allow_partial,
+            round_down: self.round_down,
+            max_attempts: self.max_attempts,
+            tap_limit: self.tap_limit,
+            max_withdrawal: self.max_withdrawal,
+            daily_limit: self.daily_limit,
+            idle_timeout: self.idle_timeout,
+            amount_scale: self.amount_scale,
+        }
+    }
+
+    /// How many standard withdrawals of `amount` the remaining cash can
+    /// cover. Zero for a zero `amount` rather than dividing by it.
+    pub fn withdrawals_remaining(&self, amount: u64) -> u64 {
+        self.cash_inside.checked_div(amount).unwrap_or(0)
+    }
+
+    /// Apply `action`, returning the successor state and the effect it
+    /// produced, if any. [`StateMachine::next_state`] is this minus the
+    /// effect.
+    pub fn transition(start: &Atm, action: &Action) -> (Atm, Option<Effect>) {
+        let (mut next, effect) = Self::dispatch(start, action);
+        // Training mode: the transition and its effects all happened,
+        // but the physical cash stays exactly where it was.
+        if start.training {
+            next.cash_inside = start.cash_inside;
+            next.usd_inside = start.usd_inside;
+            next.inventory = start.inventory.clone();
+            next.lifetime_dispensed = start.lifetime_dispensed;
+        }
+        // A customer who declined receipts gets none: transaction
+        // receipts are swallowed and nothing is kept for reprinting.
+        let mut effect = effect;
+        if !start.print_receipts
+            && matches!(
+                effect,
+                Some(
+                    Effect::Dispensed { .. }
+                        | Effect::RoundedDown { .. }
+                        | Effect::Deposited { .. }
+                        | Effect::Transferred { .. }
+                )
+            )
+        {
+            effect = None;
+            next.last_receipt = start.last_receipt.clone();
+            next.last_receipt_at = start.last_receipt_at;
+        }
+        // An action that changed nothing and said nothing was silently
+        // dropped; remember why, for drivers debugging a script.
+        if effect.is_none() && next == *start {
+            next.last_ignore_reason = IgnoreTrace(Some(Self::ignore_reason(start, action)));
+        } else {
+            next.last_ignore_reason = IgnoreTrace(None);
+        }
+        (next, effect)
+    }
+
+    /// Classify why `action` went nowhere on this machine, for
+    /// [`Atm::last_ignore_reason`].
+    fn ignore_reason(start: &Atm, action: &Action) -> IgnoreReason {
+        if !start.powered {
+            return IgnoreReason::Unpowered;
+        }
+        match action {
+            Action::PressKey(_) | Action::PressPosition(_) | Action::EnterPin(_) => {
+                match start.expected_pin_hash {
+                    Auth::Locked => IgnoreReason::MachineLocked,
+                    Auth::Waiting | Auth::CardRejected => IgnoreReason::KeyBeforeSwipe,
+                    _ => IgnoreReason::WrongState,
+                }
+            }
+            Action::InsertNote(_) => match start.expected_pin_hash {
+                Auth::Authenticated | Auth::Depositing(_) => IgnoreReason::NoteNotAccepted,
+                _ => IgnoreReason::DepositWhileUnauthenticated,
+            },
+            Action::ResetCounters
+            | Action::BlockCard(_)
+            | Action::ApproveHigherLimit(_)
+            | Action::SetDailyLimit(_)
+            | Action::OperatorBalance
+            | Action::ClearJam
+            | Action::ClearFailedAttempts
+            | Action::SetTrainingMode(_)
+                if !start.is_supervisor() =>
+            {
+                IgnoreReason::SupervisorRequired
+            }
+            _ => IgnoreReason::WrongState,
+        }
+    }
+
+    /// Like [`Atm::transition`], but with every effect of the step, not
+    /// just the primary one: a dispense that leaves the machine low on
+    /// cash also carries an [`Effect::LowCash`] warning, where the
+    /// single-effect API has no room for it.
+    pub fn next_state_with_effects(start: &Atm, action: &Action) -> (Atm, Vec<Effect>) {
+        let (next, effect) = Self::transition(start, action);
+        let mut effects: Vec<Effect> = effect.into_iter().collect();
+        if matches!(
+            effects.first(),
+            Some(Effect::Dispensed { .. } | Effect::RoundedDown { .. })
+        ) && next.cash_inside < next.max_withdrawal
+        {
+            effects.push(Effect::LowCash {
+                remaining: next.cash_inside,
+            });
+        }
+        (next, effects)
+    }
+
+    /// The action dispatch proper; [`Atm::transition`] wraps it with the
+    /// training-mode cash freeze.
+    fn dispatch(start: &Atm, action: &Action) -> (Atm, Option<Effect>) {
+        match action {
+            // Power events come first: a dark machine reacts to nothing else.
+            Action::PowerLoss => (
+                Atm {
+                    expected_pin_hash: match start.expected_pin_hash {
+                        // Lockouts survive a power cycle.
+                        Auth::Locked => Auth::Locked,
+                        _ => Auth::Waiting,
+                    },
+                    keystroke_register: Vec::new(),
+                    powered: false,
+                    ..start.clone()
+                },
+                None,
+            ),
+            Action::PowerRestore => {
+                let mut next = start.clone();
+                next.powered = true;
+                (next, None)
+            }
+            _ if !start.powered => (start.clone(), None),
+            Action::Tick => {
+                let mut next = start.clone();
+                next.now += 1;
+                Self::expire_timers(next)
+            }
+            Action::SetClock(epoch) => {
+                let mut next = start.clone();
+                next.now = *epoch;
+                Self::expire_timers(next)
+            }
+            Action::NewDay => {
+                let mut next = start.clone();
+                next.withdrawn_today = 0;
+                (next, None)
+            }
+            Action::InsertNote(note) => match start.expected_pin_hash {
+                // Only notes in the accepted denominations are swallowed;
+                // anything else bounces back out of the slot.
+                Auth::Authenticated if start.denominations.contains(note) => (
+                    Atm {
+                        expected_pin_hash: Auth::Depositing(vec![*note]),
+                        keystroke_register: Vec::new(),
+                        last_activity: start.now,
+                        ..start.clone()
+                    },
+                    None,
+                ),
+                Auth::Depositing(ref notes) if start.denominations.contains(note) => {
+                    let mut notes = notes.clone();
+                    notes.push(*note);
+                    (
+                        Atm {
+                            expected_pin_hash: Auth::Depositing(notes),
+                            last_activity: start.now,
+                            ..start.clone()
+                        },
+                        None,
+                    )
+                }
+                _ => (start.clone(), None),
+            },
+            Action::TakeCard => {
+                let mut next = start.clone();
+                next.card_inserted = false;
+                (next, None)
+            }
+            // Backing out of a deposit hands the notes straight back and
+            // resumes the session; nothing was credited yet.
+            Action::CancelDeposit => match start.expected_pin_hash {
+                Auth::Depositing(ref notes) => (
+                    Atm {
+                        expected_pin_hash: Auth::Authenticated,
+                        last_activity: start.now,
+                        ..start.clone()
+                    },
+                    Some(Effect::ReturnNotes(notes.clone())),
+                ),
+                _ => (start.clone(), None),
+            },
+            // A read-only probe of the reader, for "insert card" prompts.
+            Action::CardStatus => (start.clone(), Some(Effect::CardPresent(start.card_inserted))),
+            // Identity is read-only and answered in any state.
+            Action::Identify => (start.clone(), Some(Effect::MachineId(start.id.clone()))),
+            // The printer remembers the last receipt for a short while
+            // after the session ends.
+            Action::ReprintReceipt => match (&start.expected_pin_hash, &start.last_receipt) {
+                (Auth::Waiting, Some(receipt))
+                    if start.now.saturating_sub(start.last_receipt_at)
+                        <= Self::DEFAULT_RECEIPT_WINDOW =>
+                {
+                    (
+                        start.clone(),
+                        Some(Effect::ReceiptReprinted(receipt.clone())),
+                    )
+                }
+                _ => (start.clone(), None),
+            },
+            Action::WithdrawBills { denomination, count } => match start.expected_pin_hash {
+                Auth::Authenticated => Self::try_withdraw_bills(start, *denomination, *count),
+                _ => (start.clone(), None),
+            },
+            // Two-phase withdrawals: reserve in session, settle any time.
+            // Holds are a local-currency facility — foreign sessions
+            // cannot reserve the local pool.
+            Action::HoldFunds(amount) => match start.expected_pin_hash {
+                // The reserve binds at placement, since a capture later
+                // moves the held cash out unconditionally.
+                Auth::Authenticated
+                    if start.session_currency() == Currency::Local
+                        && *amount
+                            <= start
+                                .cash_inside
+                                .saturating_sub(start.held_amount)
+                                .saturating_sub(start.min_reserve) =>
+                {
+                    let mut next = start.clone();
+                    next.held_amount += amount;
+                    next.last_activity = start.now;
+                    (next, None)
+                }
+                _ => (start.clone(), None),
+            },
+            Action::CaptureHold => {
+                let mut next = start.clone();
+                next.cash_inside -= next.held_amount;
+                if next.held_amount > 0 {
+                    next.history.push(Transaction::Withdrawal {
+                        amount: next.held_amount,
+                        at: next.now,
+                    });
+                    next.transaction_count += 1;
+                    next.lifetime_dispensed += next.held_amount;
+                }
+                next.held_amount = 0;
+                (next, None)
+            }
+            Action::ReleaseHold => {
+                let mut next = start.clone();
+                next.held_amount = 0;
+                (next, None)
+            }
+            // Keypad faults are hardware events: they happen in any state.
+            Action::StuckKey(key) => {
+                let mut next = start.clone();
+                next.stuck_key = Some(*key);
+                (next, None)
+            }
+            Action::UnstickKey => {
+                let mut next = start.clone();
+                next.stuck_key = None;
+                (next, None)
+            }
+            // A faulty contact closes alongside whatever was pressed:
+            // the pressed key registers first, then the stuck one. The
+            // fault is lifted for the two inner steps so they cannot
+            // recurse.
+            Action::PressKey(_) if start.stuck_key.is_some() => {
+                let stuck = start.stuck_key.expect("guarded by the match arm");
+                let mut healthy = start.clone();
+                healthy.stuck_key = None;
+                let (mid, pressed_effect) = Self::transition(&healthy, action);
+                let (mut next, stuck_effect) =
+                    Self::transition(&mid, &Action::PressKey(stuck));
+                next.stuck_key = start.stuck_key;
+                (next, pressed_effect.or(stuck_effect))
+            }
+            // A jam is a hardware event, not a request: it happens in any
+            // state. Clearing it takes an operator.
+            Action::JamDispenser => {
+                let mut next = start.clone();
+                next.jammed = true;
+                (next, None)
+            }
+            Action::ClearJam => {
+                if start.is_supervisor() {
+                    let mut next = start.clone();
+                    next.jammed = false;
+                    (next, None)
+                } else {
+                    (start.clone(), None)
+                }
+            }
+            Action::SetTrainingMode(on) => {
+                if start.is_supervisor() {
+                    let mut next = start.clone();
+                    next.training = *on;
+                    (next, None)
+                } else {
+                    (start.clone(), None)
+                }
+            }
+            Action::ClearFailedAttempts => {
+                if start.is_supervisor() {
+                    let mut next = start.clone();
+                    next.failed_attempts = 0;
+                    next.last_failed_attempt_at = None;
+                    if next.expected_pin_hash == Auth::Locked {
+                        next.expected_pin_hash = Auth::Waiting;
+                    }
+                    (next, None)
+                } else {
+                    (start.clone(), None)
+                }
+            }
+            // The key switch is physical: it works regardless of auth state.
+            Action::MaintenanceKey(on) => {
+                let mut next = start.clone();
+                next.maintenance_mode = *on;
+                (next, None)
+            }
+            Action::ResetCounters => {
+                if start.is_supervisor() {
+                    let mut next = start.clone();
+                    next.transaction_count = 0;
+                    (next, None)
+                } else {
+                    (start.clone(), None)
+                }
+            }
+            Action::BlockCard(card) => {
+                if start.is_supervisor() {
+                    let mut next = start.clone();
+                    next.blocked_cards.insert(*card);
+                    (next, None)
+                } else {
+                    (start.clone(), None)
+                }
+            }
+            Action::ApproveHigherLimit(limit) => {
+                if start.is_supervisor() {
+                    let mut next = start.clone();
+                    next.elevated_limit = Some(*limit);
+                    (next, None)
+                } else {
+                    (start.clone(), None)
+                }
+            }
+            Action::SetDailyLimit(limit) => {
+                if start.is_supervisor() {
+                    let mut next = start.clone();
+                    next.daily_limit = *limit;
+                    (next, None)
+                } else {
+                    (start.clone(), None)
+                }
+            }
+            Action::OperatorBalance => {
+                if start.is_supervisor() {
+                    (
+                        start.clone(),
+                        Some(Effect::MachineCash {
+                            total: start.cash_inside,
+                            inventory: start.inventory.clone(),
+                        }),
+                    )
+                } else {
+                    (start.clone(), None)
+                }
+            }
+            Action::AuthTimeout => match start.expected_pin_hash {
+                // Mid-session the network gave up on us: abandon the
+                // operation and apologise. No cash has moved yet.
+                Auth::Authenticating(_) | Auth::Authenticated => (
+                    Atm {
+                        expected_pin_hash: Auth::Waiting,
+                        keystroke_register: Vec::new(),
+                        ..start.clone()
+                    },
+                    Some(Effect::NetworkError),
+                ),
+                // A deposit in progress has the notes physically in the
+                // machine; the network is not involved until finalization.
+                Auth::Waiting | Auth::CardRejected | Auth::Locked | Auth::Depositing(_) => {
+                    (start.clone(), None)
+                }
+            },
+            Action::Transfer { to, amount } => match start.expected_pin_hash {
+                Auth::Authenticated => Self::try_transfer(start, *to, *amount),
+                _ => (start.clone(), None),
+            },
+            // Receipt preference is a session choice.
+            Action::SetReceiptPreference(print) => match start.expected_pin_hash {
+                Auth::Authenticated => {
+                    let mut next = start.clone();
+                    next.print_receipts = *print;
+                    next.last_activity = start.now;
+                    (next, None)
+                }
+                _ => (start.clone(), None),
+            },
+            // Choosing an account only makes sense inside a session.
+            Action::SelectAccount(account) => match start.expected_pin_hash {
+                Auth::Authenticated => {
+                    let mut next = start.clone();
+                    next.selected_account = *account;
+                    next.last_activity = start.now;
+                    (next, None)
+                }
+                _ => (start.clone(), None),
+            },
+            // A receipt instead of cash: print the account balance (when
+            // one is registered) and end the session.
+            Action::BalanceReceipt => match start.expected_pin_hash {
+                Auth::Authenticated => (
+                    Atm {
+                        expected_pin_hash: Auth::Waiting,
+                        keystroke_register: Vec::new(),
+                        last_activity: start.now,
+                        ..start.clone()
+                    },
+                    Some(Effect::BalancePrinted {
+                        balance: start.current_card.and_then(|card| match start.selected_account {
+                            AccountType::Checking => start.account_balance(card),
+                            AccountType::Savings => start.savings_balance(card),
+                        }),
+                    }),
+                ),
+                _ => (start.clone(), None),
+            },
+            // Reading the history moves no cash and changes no state.
+            Action::MiniStatement(entries) => match start.expected_pin_hash {
+                Auth::Authenticated => {
+                    let entries = (*entries).min(start.history.len());
+                    let statement = start.history[start.history.len() - entries..].to_vec();
+                    (start.clone(), Some(Effect::Statement(statement)))
+                }
+                _ => (start.clone(), None),
+            },
+            // Language can be changed at any point, even while locked.
+            Action::SetLanguage(language) => {
+                let mut next = start.clone();
+                next.language = *language;
+                (next, None)
+            }
+            Action::SwipeCard(pin_hash) => match start.expected_pin_hash {
+                Auth::Waiting | Auth::CardRejected if start.blocked_cards.contains(pin_hash) => (
+                    Atm {
+                        expected_pin_hash: Auth::CardRejected,
+                        keystroke_register: Vec::new(),
+                        ..start.clone()
+                    },
+                    Some(Effect::CardBlocked),
+                ),
+                Auth::Waiting | Auth::CardRejected => {
+                    // The fraud detector watches for the same card being
+                    // swiped over and over in a short window.
+                    let mut recent_swipes = start.recent_swipes.clone();
+                    recent_swipes.retain(|(_, time)| {
+                        start.now.saturating_sub(*time) < Self::DEFAULT_SWIPE_WINDOW
+                    });
+                    recent_swipes.push((*pin_hash, start.now));
+                    let rapid = recent_swipes
+                        .iter()
+                        .filter(|(card, _)| card == pin_hash)
+                        .count();
+                    if rapid > Self::DEFAULT_MAX_RAPID_SWIPES {
+                        return (
+                            Atm {
+                                expected_pin_hash: Auth::CardRejected,
+                                keystroke_register: Vec::new(),
+                                recent_swipes,
+                                ..start.clone()
+                            },
+                            Some(Effect::SuspiciousActivity),
+                        );
+                    }
+                    // A scrambled machine deals a fresh layout for the
+                    // session, then advances its seed for the next one.
+                    let (keypad_layout, shuffle_seed) = match start.shuffle_seed {
+                        Some(seed) => {
+                            let mut rng = StdRng::seed_from_u64(seed);
+                            let mut layout = Key::digit_row();
+                            layout.shuffle(&mut rng);
+                            (layout, Some(rng.gen()))
+                        }
+                        None => (start.keypad_layout, None),
+                    };
+                    (
+                        Atm {
+                            expected_pin_hash: Auth::Authenticating(*pin_hash),
+                            keystroke_register: Vec::new(),
+                            contactless: false,
+                            card_inserted: true,
+                            chip_session: false,
+                            current_card: Some(*pin_hash),
+                            selected_account: AccountType::default(),
+                            print_receipts: true,
+                            last_activity: start.now,
+                            recent_swipes,
+                            keypad_layout,
+                            shuffle_seed,
+                            metrics: Metrics {
+                                swipes: start.metrics.swipes + 1,
+                                ..start.metrics
+                            },
+                            ..start.clone()
+                        },
+                        None,
+                    )
+                }
+                // Swiping mid-session or while locked does nothing.
+                _ => (start.clone(), None),
+            },
+            // A dipped chip card opens a session that stays open — and
+            // keeps the card — until it is removed.
+            Action::InsertChipCard(card) => {
+                let (mut next, effect) = Self::transition(start, &Action::SwipeCard(*card));
+                if matches!(next.expected_pin_hash, Auth::Authenticating(_)) {
+                    next.chip_session = true;
+                }
+                (next, effect)
+            }
+            // Pulling the chip card ends whatever the session was doing.
+            Action::RemoveCard => {
+                let mut next = start.clone();
+                next.card_inserted = false;
+                next.chip_session = false;
+                // Notes escrowed mid-deposit come back uncredited, as a
+                // cancel would hand them back — they never vanish.
+                let effect = match next.expected_pin_hash {
+                    Auth::Depositing(ref notes) => Some(Effect::ReturnNotes(notes.clone())),
+                    _ => None,
+                };
+                if matches!(
+                    next.expected_pin_hash,
+                    Auth::Authenticating(_) | Auth::Authenticated | Auth::Depositing(_)
+                ) {
+                    next.expected_pin_hash = Auth::Waiting;
+                    next.keystroke_register = Vec::new();
+                }
+                (next, effect)
+            }
+            // A partial read needs a matching second one inside the
+            // window; two halves make a whole swipe.
+            Action::PartialSwipe(card) => match start.expected_pin_hash {
+                Auth::Waiting | Auth::CardRejected => match start.pending_swipe {
+                    Some((pending, at))
+                        if pending == *card
+                            && start.now.saturating_sub(at) <= Self::DEFAULT_RESWIPE_WINDOW =>
+                    {
+                        let (mut next, effect) = Self::transition(start, &Action::SwipeCard(*card));
+                        next.pending_swipe = None;
+                        (next, effect)
+                    }
+                    _ => {
+                        let mut next = start.clone();
+                        next.pending_swipe = Some((*card, start.now));
+                        (next, Some(Effect::ReswipeNeeded))
+                    }
+                },
+                _ => (start.clone(), None),
+            },
+            // A full swipe first checks the expiry against the machine
+            // clock, then behaves exactly like a plain swipe.
+            Action::SwipeCardFull { number, expiry } => match start.expected_pin_hash {
+                Auth::Waiting | Auth::CardRejected if *expiry < start.now => (
+                    Atm {
+                        expected_pin_hash: Auth::CardRejected,
+                        keystroke_register: Vec::new(),
+                        ..start.clone()
+                    },
+                    Some(Effect::CardExpired),
+                ),
+                _ => Self::transition(start, &Action::SwipeCard(*number)),
+            },
+            Action::TapCard(card) => match start.expected_pin_hash {
+                Auth::Waiting | Auth::CardRejected if start.blocked_cards.contains(card) => (
+                    Atm {
+                        expected_pin_hash: Auth::CardRejected,
+                        keystroke_register: Vec::new(),
+                        ..start.clone()
+                    },
+                    Some(Effect::CardBlocked),
+                ),
+                // A tap authenticates immediately — the chip vouched for the
+                // holder — but the session stays capped at the tap limit.
+                Auth::Waiting | Auth::CardRejected => (
+                    Atm {
+                        expected_pin_hash: Auth::Authenticated,
+                        keystroke_register: Vec::new(),
+                        contactless: true,
+                        chip_session: false,
+                        current_card: Some(*card),
+                        selected_account: AccountType::default(),
+                        print_receipts: true,
+                        last_activity: start.now,
+                        metrics: Metrics {
+                            swipes: start.metrics.swipes + 1,
+                            ..start.metrics
+                        },
+                        ..start.clone()
+                    },
+                    None,
+                ),
+                _ => (start.clone(), None),
+            },
+            // A batched PIN attempt is sugar for pressing each key and
+            // then `Enter`, so it inherits all the per-key semantics.
+            Action::EnterPin(keys) => match start.expected_pin_hash {
+                Auth::Authenticating(_) => {
+                    let mut state = start.clone();
+                    let mut last_effect = None;
+                    for key in keys.iter().chain(std::iter::once(&Key::Enter)) {
+                        let (next, effect) = Self::transition(&state, &Action::PressKey(*key));
+                        state = next;
+                        if effect.is_some() {
+                            last_effect = effect;
+                        }
+                    }
+                    (state, last_effect)
+                }
+                _ => (start.clone(), None),
+            },
+            // A touched position is just the key sitting there this
+            // session; out-of-range positions touch nothing.
+            Action::PressPosition(position) => {
+                match start.keypad_layout.get(usize::from(*position)) {
+                    Some(key) => Self::transition(start, &Action::PressKey(*key)),
+                    None => (start.clone(), None),
+                }
+            }
+            Action::PressKey(key) => match start.expected_pin_hash {
+                Auth::Authenticating(expected) => {
+                    if *key == Key::Enter {
+                        Self::check_pin(start, expected)
+                    } else {
+                        (Self::push_key(start, *key), None)
+                    }
+                }
+                Auth::Authenticated => {
+                    if *key == Key::Enter {
+                        if start.keystroke_register.is_empty() {
+                            // A bare `Enter` with no amount keyed is a
+                            // slip of the finger, not a failed
+                            // withdrawal: stay in the session.
+                            let mut next = start.clone();
+                            next.metrics.keypresses += 1;
+                            next.last_activity = start.now;
+                            (next, None)
+                        } else {
+                            Self::try_withdraw(start)
+                        }
+                    } else {
+                        (Self::push_key(start, *key), None)
+                    }
+                }
+                Auth::Depositing(ref notes) => {
+                    if *key == Key::Enter {
+                        Self::finalize_deposit(start, notes.iter().sum())
+                    } else {
+                        // Only the deposit slot matters now; digits are
+                        // ignored.
+                        (start.clone(), None)
+                    }
+                }
+                // Keys before a swipe, or on a locked machine, are ignored.
+                Auth::Waiting | Auth::CardRejected | Auth::Locked => (start.clone(), None),
+            },
+        }
+    }
+
+    /// Apply the time-based transitions after the clock moved (by a tick
+    /// or an absolute set): abandon idle sessions and swallow forgotten
+    /// cards. Elapsed time saturates, so winding the clock backwards just
+    /// defers the timers.
+    fn expire_timers(mut next: Atm) -> (Atm, Option<Effect>) {
+        let idle = next.now.saturating_sub(next.last_activity);
+        let in_session = matches!(
+            next.expected_pin_hash,
+            Auth::Authenticating(_) | Auth::Authenticated
+        );
+        if in_session && idle >= next.idle_timeout {
+            next.expected_pin_hash = Auth::Waiting;
+            next.keystroke_register.clear();
+            next.chip_session = false;
+        }
+        // A partial read that never got its confirming re-swipe lapses.
+        if let Some((_, at)) = next.pending_swipe {
+            if next.now.saturating_sub(at) > Self::DEFAULT_RESWIPE_WINDOW {
+                next.pending_swipe = None;
+            }
+        }
+        // A card forgotten in the reader after the session ended is
+        // swallowed once the card timeout elapses.
+        if next.expected_pin_hash == Auth::Waiting && next.card_inserted && idle >= next.card_timeout
+        {
+            next.card_inserted = false;
+            return (next, Some(Effect::CardRetained));
+        }
+        (next, None)
+    }
+
+    /// Record a keystroke during PIN or amount entry.
+    fn push_key(start: &Atm, key: Key) -> Atm {
+        let mut register = start.keystroke_register.clone();
+        register.push(key);
+        Atm {
+            keystroke_register: register,
+            last_activity: start.now,
+            metrics: Metrics {
+                keypresses: start.metrics.keypresses + 1,
+                ..start.metrics
+            },
+            ..start.clone()
+        }
+    }
+
+    /// The keystroke register with each mapped key replaced by the key
+    /// its configured digit normally lives on, so card hashes keep
+    /// meaning "the PIN digits" whatever the physical layout. Unmapped
+    /// keys pass through untouched.
+    fn canonicalize_keys(&self) -> Vec<Key> {
+        self.keystroke_register
+            .iter()
+            .map(|key| {
+                self.digit_map
+                    .get(key)
+                    .and_then(|digit| Key::from_digit(u32::from(*digit)))
+                    .unwrap_or(*key)
+            })
+            .collect()
+    }
+
+    /// `Enter` while authenticating: compare the entered PIN's hash against
+    /// the one the card promised.
+    ///
+    /// Authentication never touches `cash_inside` — there is no
+    /// authentication fee — so it is possible even on an empty machine;
+    /// only the subsequent withdrawal checks cash.
+    fn check_pin(start: &Atm, expected: u64) -> (Atm, Option<Effect>) {
+        let keypresses = start.metrics.keypresses + 1;
+        // Keying a PIN — right or wrong — is customer behaviour, not the
+        // swipe-and-abandon loop the fraud detector watches for.
+        let mut recent_swipes = start.recent_swipes.clone();
+        recent_swipes.retain(|(card, _)| *card != expected);
+        if start.pin_hasher.0.hash(&start.canonicalize_keys()) == expected {
+            // The configured authentication policy may book a fee against
+            // the account — through the shared ledger when one is
+            // injected; physical cash never moves here.
+            let mut accounts = start.accounts.clone();
+            // Training mode books nothing, like every other settle.
+            if let AuthEffect::ChargeFee(fee) = start.on_auth {
+                if !start.training {
+                    if let (Some(ledger), Some(card)) = (&start.ledger, start.current_card) {
+                        ledger.0.debit(card, fee);
+                    } else if let Some(balance) =
+                        start.current_card.and_then(|card| accounts.get_mut(&card))
+                    {
+                        *balance = balance.saturating_sub(fee);
+                    }
+                }
+            }
+            (
+                Atm {
+                    expected_pin_hash: Auth::Authenticated,
+                    keystroke_register: Vec::new(),
+                    failed_attempts: 0,
+                    last_failed_attempt_at: None,
+                    last_activity: start.now,
+                    accounts,
+                    recent_swipes,
+                    metrics: Metrics {
+                        keypresses,
+                        ..start.metrics
+                    },
+                    ..start.clone()
+                },
+                None,
+            )
+        } else {
+            let failed_attempts = start.failed_attempts + 1;
+            let auth = if failed_attempts >= start.max_attempts {
+                Auth::Locked
+            } else {
+                Auth::Waiting
+            };
+            (
+                Atm {
+                    expected_pin_hash: auth,
+                    keystroke_register: Vec::new(),
+                    failed_attempts,
+                    last_failed_attempt_at: Some(start.now),
+                    lifetime_failed_pins: start.lifetime_failed_pins + 1,
+                    last_activity: start.now,
+                    recent_swipes,
+                    metrics: Metrics {
+                        keypresses,
+                        failures: start.metrics.failures + 1,
+                        ..start.metrics
+                    },
+                    ..start.clone()
+                },
+                Some(Effect::WrongPin {
+                    attempts_remaining: start.max_attempts.saturating_sub(failed_attempts),
+                }),
+            )
+        }
+    }
+
+    /// `Enter` while authenticated: parse the keyed amount and dispense it
+    /// if every check passes. Any failure quietly ends the session.
+    ///
+    /// A foreign card's session runs the identical checks and planning,
+    /// but against the USD pool: the machine is viewed with that pool as
+    /// its cash (foreign bills are not inventory-tracked), and the result
+    /// is folded back so the local pool is untouched.
+    fn try_withdraw(start: &Atm) -> (Atm, Option<Effect>) {
+        if start.jammed {
+            return Self::jam_abort(start);
+        }
+        match start.session_currency() {
+            Currency::Local => Self::try_withdraw_pool(start),
+            Currency::Usd => {
+                let viewed = Atm {
+                    cash_inside: start.usd_inside,
+                    inventory: HashMap::new(),
+                    // Holds are a local-currency facility; they do not
+                    // encumber the USD pool.
+                    held_amount: 0,
+                    ..start.clone()
+                };
+                let (mut next, effect) = Self::try_withdraw_pool(&viewed);
+                next.usd_inside = next.cash_inside;
+                next.cash_inside = start.cash_inside;
+                next.inventory = start.inventory.clone();
+                next.held_amount = start.held_amount;
+                (next, effect)
+            }
+        }
+    }
+
+    /// A withdrawal with a configured fee, in the configured order (see
+    /// [`FeeOrder`]). `None` means the request cannot be satisfied and
+    /// the caller should abort as usual.
+    fn withdraw_with_fee(start: &Atm, requested: u64) -> Option<(Atm, Option<Effect>)> {
+        let fee = start.withdrawal_fee;
+        let target = match start.fee_order {
+            FeeOrder::FeeThenRound => requested.checked_sub(fee)?,
+            FeeOrder::RoundThenFee => {
+                let rounded: u64 = start.plan_withdrawal(requested).ok()?.iter().sum();
+                rounded.checked_sub(fee)?
+            }
+        };
+        let bills = start.plan_withdrawal(target).ok()?;
+        let payout: u64 = bills.iter().sum();
+        if payout == 0 {
+            return None;
+        }
+
+        // Settle: the machine parts with the payout, the account with
+        // payout + fee.
+        let mut inventory = start.inventory.clone();
+        for bill in &bills {
+            if let Some(count) = inventory.get_mut(bill) {
+                *count -= 1;
+            }
+        }
+        let (accounts, savings_accounts) = start.settle_account(true, payout + fee);
+        let mut history = start.history.clone();
+        history.push(Transaction::Withdrawal {
+            amount: payout + fee,
+            at: start.now,
+        });
+
+        // A short payout on a round-down machine is announced, exactly
+        // as on the fee-free path.
+        let effect = if payout < requested && start.round_down {
+            Effect::RoundedDown {
+                requested,
+                dispensed: payout,
+            }
+        } else {
+            Effect::Dispensed {
+                amount: payout,
+                bills,
+                balance_after: start.cash_inside - payout,
+            }
+        };
+        Some((
+            Atm {
+                cash_inside: start.cash_inside - payout,
+                withdrawn_today: start.withdrawn_today + payout + fee,
+                lifetime_dispensed: start.lifetime_dispensed + payout,
+                transaction_count: start.transaction_count + 1,
+                accounts,
+                savings_accounts,
+                history,
+                inventory,
+                last_receipt: Some(start.message(&effect)),
+                last_receipt_at: start.now,
+                elevated_limit: None,
+                expected_pin_hash: if start.single_transaction && !start.chip_session {
+                    Auth::Waiting
+                } else {
+                    Auth::Authenticated
+                },
+                keystroke_register: Vec::new(),
+                last_activity: start.now,
+                metrics: Metrics {
+                    keypresses: start.metrics.keypresses + 1,
+                    withdrawals: start.metrics.withdrawals + 1,
+                    ..start.metrics
+                },
+                ..start.clone()
+            },
+            Some(effect),
+        ))
+    }
+
+    /// A balance transfer while authenticated: debit the selected
+    /// account, credit the target's checking account (or its ledger
+    /// balance). The session stays open — no cash moved — and a refused
+    /// transfer (no funds, unknown target, zero amount) changes nothing.
+    fn try_transfer(start: &Atm, to: u64, amount: u64) -> (Atm, Option<Effect>) {
+        let refused = || (start.clone(), None);
+        if amount == 0 || start.check_account_funds(amount).is_err() {
+            return refused();
+        }
+        // Unlike withdrawals — where the machine's own cash is the real
+        // guard — a transfer moves pure account money, so the source
+        // must actually have an account, not merely be unchecked.
+        let source_known = match (&start.ledger, start.current_card) {
+            (Some(ledger), Some(card)) => ledger.0.balance(card).is_some(),
+            (None, Some(card)) => match start.selected_account {
+                AccountType::Checking => start.accounts.contains_key(&card),
+                AccountType::Savings => start.savings_accounts.contains_key(&card),
+            },
+            _ => false,
+        };
+        let target_exists = match &start.ledger {
+            Some(ledger) => ledger.0.balance(to).is_some(),
+            None => start.accounts.contains_key(&to),
+        };
+        if !source_known || !target_exists {
+            return refused();
+        }
+        let (mut accounts, savings_accounts) = start.settle_account(true, amount);
+        if !start.training {
+            match &start.ledger {
+                Some(ledger) => ledger.0.credit(to, amount),
+                None => {
+                    if let Some(balance) = accounts.get_mut(&to) {
+                        *balance += amount;
+                    }
+                }
+            }
+        }
+        (
+            Atm {
+                accounts,
+                savings_accounts,
+                transaction_count: start.transaction_count + 1,
+                last_activity: start.now,
+                ..start.clone()
+            },
+            Some(Effect::Transferred { to, amount }),
+        )
+    }
+
+    /// A withdrawal attempt on a jammed dispenser: the session ends,
+    /// nothing is debited, and the customer is told why.
+    fn jam_abort(start: &Atm) -> (Atm, Option<Effect>) {
+        (
+            Atm {
+                expected_pin_hash: Auth::Waiting,
+                keystroke_register: Vec::new(),
+                metrics: Metrics {
+                    failures: start.metrics.failures + 1,
+                    ..start.metrics
+                },
+                ..start.clone()
+            },
+            Some(Effect::DispenserJam),
+        )
+    }
+
+    /// The withdrawal proper, against whichever pool the caller has
+    /// pointed `cash_inside` at.
+    fn try_withdraw_pool(start: &Atm) -> (Atm, Option<Effect>) {
+        let abort = || {
+            (
+                Atm {
+                    expected_pin_hash: Auth::Waiting,
+                    keystroke_register: Vec::new(),
+                    metrics: Metrics {
+                        keypresses: start.metrics.keypresses + 1,
+                        failures: start.metrics.failures + 1,
+                        ..start.metrics
+                    },
+                    ..start.clone()
+                },
+                None,
+            )
+        };
+
+        let Some(requested) =
+            parse_amount(&start.keystroke_register, start.amount_scale, &start.digit_map)
+        else {
+            return abort();
+        };
+        // Customers cannot withdraw while an operator is servicing the
+        // machine.
+        if start.maintenance_mode {
+            return abort();
+        }
+        // The operator's one-time approval or the card's tier limit
+        // may override the machine maximum.
+        if requested > start.per_withdrawal_limit()
+            || start.withdrawn_today + requested > start.daily_limit
+            || (start.contactless && requested > start.tap_limit)
+        {
+            return abort();
+        }
+
+        if start.check_account_funds(requested).is_err() {
+            return abort();
+        }
+        // Injected business rules get the same veto as built-in checks.
+        if start.check_rules(requested).is_err() {
+            return abort();
+        }
+        // A configured fee changes the arithmetic; the free path below
+        // stays untouched.
+        if start.withdrawal_fee > 0 {
+            return Self::withdraw_with_fee(start, requested).unwrap_or_else(abort);
+        }
+        let Ok(bills) = start.plan_withdrawal(requested) else {
+            return abort();
+        };
+        let amount: u64 = bills.iter().sum();
+        // Hand the chosen bills out of the inventory, when one is kept.
+        let mut inventory = start.inventory.clone();
+        for bill in &bills {
+            if let Some(count) = inventory.get_mut(bill) {
+                *count -= 1;
+            }
+        }
+        let effect = if amount < requested && start.round_down {
+            Effect::RoundedDown {
+                requested,
+                dispensed: amount,
+            }
+        } else {
+            Effect::Dispensed {
+                amount,
+                bills,
+                // The balance the receipt shows is the cash left after
+                // this very dispense.
+                balance_after: start.cash_inside - amount,
+            }
+        };
+
+        // Debit the selected account (when registered) alongside the
+        // machine cash.
+        let (accounts, savings_accounts) = start.settle_account(true, amount);
+        let mut history = start.history.clone();
+        history.push(Transaction::Withdrawal {
+            amount,
+            at: start.now,
+        });
+
+        (
+            Atm {
+                cash_inside: start.cash_inside - amount,
+                withdrawn_today: start.withdrawn_today + amount,
+                lifetime_dispensed: start.lifetime_dispensed + amount,
+                transaction_count: start.transaction_count + 1,
+                accounts,
+                savings_accounts,
+                history,
+                inventory,
+                last_receipt: Some(start.message(&effect)),
+                last_receipt_at: start.now,
+                // An approved one-time limit is spent by this dispense.
+                elevated_limit: None,
+                expected_pin_hash: if start.single_transaction && !start.chip_session {
+                    Auth::Waiting
+                } else {
+                    Auth::Authenticated
+                },
+                keystroke_register: Vec::new(),
+                last_activity: start.now,
+                metrics: Metrics {
+                    keypresses: start.metrics.keypresses + 1,
+                    withdrawals: start.metrics.withdrawals + 1,
+                    ..start.metrics
+                },
+                ..start.clone()
+            },
+            Some(effect),
+        )
+    }
+
+    /// An exact-bills withdrawal — "four $5 bills" — while authenticated.
+    /// Runs the same limit, account and pool checks as a keyed amount,
+    /// plus the obvious one: the machine must actually stock that many of
+    /// that bill. Like any withdrawal it ends the session either way.
+    fn try_withdraw_bills(start: &Atm, denomination: u64, count: u64) -> (Atm, Option<Effect>) {
+        if start.jammed {
+            return Self::jam_abort(start);
+        }
+        match start.session_currency() {
+            Currency::Local => Self::try_withdraw_bills_pool(start, denomination, count),
+            Currency::Usd => {
+                let viewed = Atm {
+                    cash_inside: start.usd_inside,
+                    inventory: HashMap::new(),
+                    // Holds are a local-currency facility; they do not
+                    // encumber the USD pool.
+                    held_amount: 0,
+                    ..start.clone()
+                };
+                let (mut next, effect) = Self::try_withdraw_bills_pool(&viewed, denomination, count);
+                next.usd_inside = next.cash_inside;
+                next.cash_inside = start.cash_inside;
+                next.inventory = start.inventory.clone();
+                next.held_amount = start.held_amount;
+                (next, effect)
+            }
+        }
+    }
+
+    /// The exact-bills withdrawal proper, against whichever pool the
+    /// caller has pointed `cash_inside` at.
+    fn try_withdraw_bills_pool(start: &Atm, denomination: u64, count: u64) -> (Atm, Option<Effect>) {
+        let abort = || {
+            (
+                Atm {
+                    expected_pin_hash: Auth::Waiting,
+                    keystroke_register: Vec::new(),
+                    metrics: Metrics {
+                        failures: start.metrics.failures + 1,
+                        ..start.metrics
+                    },
+                    ..start.clone()
+                },
+                None,
+            )
+        };
+
+        let Some(amount) = denomination.checked_mul(count) else {
+            return abort();
+        };
+        if amount == 0 || !start.denominations.contains(&denomination) {
+            return abort();
+        }
+        if start.maintenance_mode {
+            return abort();
+        }
+        if amount > start.per_withdrawal_limit()
+            || start.withdrawn_today + amount > start.daily_limit
+            || (start.contactless && amount > start.tap_limit)
+        {
+            return abort();
+        }
+        if start.check_account_funds(amount).is_err() {
+            return abort();
+        }
+        if start.check_rules(amount).is_err() {
+            return abort();
+        }
+        let available = start.cash_inside.saturating_sub(start.held_amount);
+        if amount > available || available - amount < start.min_reserve {
+            return abort();
+        }
+        // An empty inventory is the flat-cash model: bills unlimited.
+        let mut inventory = start.inventory.clone();
+        if !inventory.is_empty() {
+            match inventory.get_mut(&denomination) {
+                Some(stocked) if *stocked >= count => *stocked -= count,
+                _ => return abort(),
+            }
+        }
+
+        let bills = vec![denomination; count as usize];
+        let (accounts, savings_accounts) = start.settle_account(true, amount);
+        let mut history = start.history.clone();
+        history.push(Transaction::Withdrawal {
+            amount,
+            at: start.now,
+        });
+
+        let effect = Effect::Dispensed {
+            amount,
+            bills,
+            balance_after: start.cash_inside - amount,
+        };
+        (
+            Atm {
+                cash_inside: start.cash_inside - amount,
+                withdrawn_today: start.withdrawn_today + amount,
+                lifetime_dispensed: start.lifetime_dispensed + amount,
+                transaction_count: start.transaction_count + 1,
+                accounts,
+                savings_accounts,
+                history,
+                inventory,
+                last_receipt: Some(start.message(&effect)),
+                last_receipt_at: start.now,
+                // An approved one-time limit is spent by this dispense.
+                elevated_limit: None,
+                expected_pin_hash: if start.single_transaction && !start.chip_session {
+                    Auth::Waiting
+                } else {
+                    Auth::Authenticated
+                },
+                keystroke_register: Vec::new(),
+                last_activity: start.now,
+                metrics: Metrics {
+                    withdrawals: start.metrics.withdrawals + 1,
+                    ..start.metrics
+                },
+                ..start.clone()
+            },
+            Some(effect),
+        )
+    }
+
+    /// The text the screen shows for the current state. Centralizes the
+    /// UX strings so every driver prompts identically.
+    pub fn prompt(&self) -> &'static str {
+        if !self.powered {
+            return "Out of service";
+        }
+        if self.maintenance_mode {
+            return "In maintenance; please use another machine";
+        }
+        match self.expected_pin_hash {
+            Auth::Waiting => "Please swipe your card",
+            Auth::Authenticating(_) => "Enter PIN",
+            Auth::Authenticated => "Enter amount",
+            Auth::Depositing(_) => "Insert notes, or press Enter to finish",
+            Auth::CardRejected => "Card refused; contact your bank",
+            Auth::Locked => "Machine locked; contact your bank",
+        }
+    }
+
+    /// Apply `action` to this machine in place, returning the effect —
+    /// [`Atm::transition`] without the fresh state.
+    ///
+    /// Behaviour is identical; the point is cost. The pure transition
+    /// clones the whole state (keystroke register included) even for
+    /// ignored input, so tight drivers that own their machine can use
+    /// this instead: the common keypress paths mutate without cloning
+    /// anything, and only the rarer transitions fall back to the pure
+    /// function.
+    pub fn advance(&mut self, action: &Action) -> Option<Effect> {
+        if let Action::PressKey(key) = action {
+            // A stuck key doubles input; leave that to the full dispatch.
+            if self.powered && self.stuck_key.is_none() {
+                match self.expected_pin_hash {
+                    // Keys before a swipe, or on a locked machine, are
+                    // ignored: nothing to build, nothing to clone.
+                    Auth::Waiting | Auth::CardRejected | Auth::Locked => {
+                        self.last_ignore_reason =
+                            IgnoreTrace(Some(Self::ignore_reason(self, action)));
+                        return None;
+                    }
+                    // A non-`Enter` key in session just lands in the
+                    // register, as in `push_key`.
+                    Auth::Authenticating(_) | Auth::Authenticated if *key != Key::Enter => {
+                        self.keystroke_register.push(*key);
+                        self.last_activity = self.now;
+                        self.metrics.keypresses += 1;
+                        self.last_ignore_reason = IgnoreTrace(None);
+                        return None;
+                    }
+                    // A bare `Enter` with no amount keyed is a no-op slip.
+                    Auth::Authenticated if self.keystroke_register.is_empty() => {
+                        self.last_activity = self.now;
+                        self.metrics.keypresses += 1;
+                        self.last_ignore_reason = IgnoreTrace(None);
+                        return None;
+                    }
+                    // While depositing only the note slot matters; digits
+                    // are ignored.
+                    Auth::Depositing(_) if *key != Key::Enter => {
+                        self.last_ignore_reason =
+                            IgnoreTrace(Some(Self::ignore_reason(self, action)));
+                        return None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let (next, effect) = Atm::transition(self, action);
+        *self = next;
+        effect
+    }
+
+    /// The state this machine would be in after `action` — a clarifying
+    /// alias for [`StateMachine::next_state`], which already returns a new
+    /// state and never mutates `self`.
+    pub fn preview(&self, action: &Action) -> Atm {
+        Self::next_state(self, action)
+    }
+
+    /// The machine balance a withdrawal of `amount` would leave behind,
+    /// without applying it — for UIs showing "you'll have $X left".
+    pub fn preview_withdrawal(&self, amount: u64) -> Result<u64, AtmError> {
+        // A foreign session previews against the USD pool, just as the
+        // withdrawal itself will run.
+        if self.session_currency() == Currency::Usd {
+            let viewed = Atm {
+                cash_inside: self.usd_inside,
+                inventory: HashMap::new(),
+                // Holds never encumber the USD pool.
+                held_amount: 0,
+                // Un-mark the card so the recursion lands in the local arm.
+                foreign_cards: HashSet::new(),
+                ..self.clone()
+            };
+            return viewed.preview_withdrawal(amount);
+        }
+        self.check_account_funds(amount)?;
+        self.check_rules(amount)?;
+        let bills = self.plan_withdrawal(amount)?;
+        Ok(self.cash_inside - bills.iter().sum::<u64>())
+    }
+
+    /// Check the session card's account (when one is registered) against a
+    /// withdrawal of `amount`. Both this and the machine's own cash must
+    /// pass before anything is dispensed.
+    fn check_account_funds(&self, amount: u64) -> Result<(), AtmError> {
+        if let (Some(ledger), Some(card)) = (&self.ledger, self.current_card) {
+            return match ledger.0.balance(card) {
+                Some(balance) if balance < amount => Err(AtmError::InsufficientAccountFunds),
+                _ => Ok(()),
+            };
+        }
+        let accounts = match self.selected_account {
+            AccountType::Checking => &self.accounts,
+            AccountType::Savings => &self.savings_accounts,
+        };
+        match self.current_card.and_then(|card| accounts.get(&card)) {
+            Some(balance) if *balance < amount => Err(AtmError::InsufficientAccountFunds),
+            _ => Ok(()),
+        }
+    }
+
+    /// `Enter` while depositing: credit the inserted notes to the machine
+    /// and, when one is registered, the session card's account. Deposited
+    /// notes go to a vault, not the dispensing inventory, so only
+    /// `cash_inside` grows here.
+    fn finalize_deposit(start: &Atm, amount: u64) -> (Atm, Option<Effect>) {
+        let (accounts, savings_accounts) = start.settle_account(false, amount);
+        let mut history = start.history.clone();
+        history.push(Transaction::Deposit {
+            amount,
+            at: start.now,
+        });
+        let effect = Effect::Deposited { amount };
+        (
+            Atm {
+                cash_inside: start.cash_inside + amount,
+                transaction_count: start.transaction_count + 1,
+                expected_pin_hash: Auth::Waiting,
+                keystroke_register: Vec::new(),
+                last_activity: start.now,
+                accounts,
+                savings_accounts,
+                history,
+                last_receipt: Some(start.message(&effect)),
+                last_receipt_at: start.now,
+                ..start.clone()
+            },
+            Some(effect),
+        )
+    }
+
+    /// Plan the bills for a withdrawal of `requested`, checking cash on
+    /// hand and exact formability (unless partial dispensing is allowed).
+    ///
+    /// This is the dispensing half of a withdrawal without the session
+    /// bookkeeping, so UIs can ask "would this work?" and distinguish an
+    /// empty machine from an unrepresentable amount.
+    pub fn plan_withdrawal(&self, requested: u64) -> Result<Vec<u64>, AtmError> {
+        if requested == 0 {
+            return Err(AtmError::InvalidAmount);
+        }
+        // Cash under a pre-authorization hold is spoken for.
+        let available = self.cash_inside.saturating_sub(self.held_amount);
+        if requested > available {
+            return Err(AtmError::MachineOutOfCash);
+        }
+        // Neither may a withdrawal cut into the configured float.
+        if available - requested < self.min_reserve {
+            return Err(AtmError::BelowReserve);
+        }
+        let bills = self.select_bills(requested);
+        let formable: u64 = bills.iter().sum();
+        if formable == requested || ((self.allow_partial || self.round_down) && formable > 0) {
+            Ok(bills)
+        } else {
+            Err(AtmError::CannotMakeExactAmount { requested })
+        }
+    }
+
+    /// Greedily select bills for `amount` under the dispense policy.
+    ///
+    /// The selection's total may fall short of `amount` when it is not
+    /// exactly formable greedily; the caller decides whether a shortfall
+    /// is acceptable.
+    fn select_bills(&self, amount: u64) -> Vec<u64> {
+        // `denominations` is kept sorted largest-first; for small-bills-first
+        // we simply walk it the other way.
+        let order: Vec<u64> = match self.dispense_policy {
+            DispensePolicy::FewestBills => self.denominations.clone(),
+            DispensePolicy::SmallBillsFirst => {
+                self.denominations.iter().rev().copied().collect()
+            }
+        };
+        let mut bills = Vec::new();
+        let mut remaining = amount;
+        for denomination in order {
+            if denomination == 0 {
+                continue;
+            }
+            // An empty inventory is the flat-cash model: bills unlimited.
+            let mut available = if self.inventory.is_empty() {
+                u64::MAX
+            } else {
+                self.inventory.get(&denomination).copied().unwrap_or(0)
+            };
+            while remaining >= denomination && available > 0 {
+                bills.push(denomination);
+                remaining -= denomination;
+                available -= 1;
+            }
+        }
+        bills
+    }
+}
+
+/// The keystrokes that request a withdrawal of `amount`: its digits in
+/// order, terminated by `Enter`. The inverse of amount parsing, for tests
+/// and drivers that want to key in arbitrary amounts concisely.
+pub fn withdrawal_keys(amount: u64) -> Vec<Key> {
+    let mut keys: Vec<Key> = amount
+        .to_string()
+        .chars()
+        .map(|c| {
+            let digit = c.to_digit(10).expect("u64 renders as digits");
+            Key::from_digit(digit).expect("digit has a key")
+        })
+        .collect();
+    keys.push(Key::Enter);
+    keys
+}
+
+/// Drive `start` through `n` randomly chosen actions — swipes and PIN
+/// attempts on a demo card, keypresses, notes, ticks — for stress tests
+/// and benchmarks of the transition function.
+///
+/// The sequence is fully determined by `seed`, so a failure can be
+/// replayed by rerunning with the same seed.
+pub fn simulate_random(start: Atm, n: usize, seed: u64) -> Atm {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let pin = [Key::One, Key::Two, Key::Three, Key::Four];
+    let card = hash_pin(&pin);
+    let mut atm = start;
+    for _ in 0..n {
+        let action = match rng.gen_range(0..10) {
+            0 => Action::SwipeCard(card),
+            1 => Action::EnterPin(pin.to_vec()),
+            // Keypresses carry most of the weight: they do most of the
+            // work in a real session too.
+            2..=5 => Action::PressKey(Key::all()[rng.gen_range(0..Key::all().len())]),
+            6 => Action::Tick,
+            7 => Action::InsertNote(10),
+            8 => Action::TakeCard,
+            _ => Action::AuthTimeout,
+        };
+        atm = Atm::next_state(&atm, &action);
+    }
+    atm
+}
+
+/// Parse the keys keyed so far into an amount in the machine's minor
+/// units, where `scale` is the number of decimal places a `Dot` may
+/// introduce (e.g. scale 2 means cents: `1 . 5 0` is 150).
+///
+/// Leading zeros are harmless — `0 2 0` means 20 — because digits just
+/// accumulate positionally. `None` when no digits were entered, the
+/// amount is zero (so a lone `0` is rejected, not dispensed), a second
+/// `Dot` appears, more fraction digits than `scale` are keyed, or a
+/// `Dot` is used on a machine whose scale is zero.
+fn parse_amount(keys: &[Key], scale: u32, digit_map: &HashMap<Key, u8>) -> Option<u64> {
+    let mut amount: u64 = 0;
+    let mut saw_digit = false;
+    let mut fraction_digits: Option<u32> = None;
+    for key in keys {
+        match key {
+            Key::Dot => {
+                if fraction_digits.is_some() || scale == 0 {
+                    return None;
+                }
+                fraction_digits = Some(0);
+            }
+            _ => {
+                let digit = u64::from(*digit_map.get(key)?);
+                if let Some(count) = fraction_digits.as_mut() {
+                    if *count == scale {
+                        return None;
+                    }
+                    *count += 1;
+                }
+                // A long enough digit sequence would overflow u64; treat
+                // it as any other invalid amount rather than wrapping.
+                amount = amount.checked_mul(10)?.checked_add(digit)?;
+                saw_digit = true;
+            }
+        }
+    }
+    // Scale up to minor units for however many fraction digits are missing:
+    // with scale 2, `1 . 5` and `1 5 0` both mean 150.
+    // `checked_pow` as well as `checked_mul`: an absurd configured scale
+    // is just another way to make an amount unrepresentable.
+    amount = amount.checked_mul(10u64.checked_pow(scale - fraction_digits.unwrap_or(0))?)?;
+    if saw_digit && amount > 0 {
+        Some(amount)
+    } else {
+        None
+    }
+}
+
+/// Masks the keystroke register while a PIN is being entered — debug
+/// logs must never leak PIN digits — but shows the digits of an amount
+/// being typed, which are not secret. Config fields are elided.
+impl fmt::Debug for Atm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Atm");
+        s.field("cash_inside", &self.cash_inside)
+            .field("auth", &self.expected_pin_hash);
+        if matches!(self.expected_pin_hash, Auth::Authenticating(_)) {
+            s.field(
+                "keystroke_register",
+                &format_args!("[*; {}]", self.keystroke_register.len()),
+            );
+        } else {
+            s.field("keystroke_register", &self.keystroke_register);
+        }
+        s.field("failed_attempts", &self.failed_attempts)
+            .field("transaction_count", &self.transaction_count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StateMachine for Atm {
+    type State = Atm;
+    type Transition = Action;
+
+    fn next_state(starting_state: &Self::State, transition: &Self::Transition) -> Self::State {
+        Atm::transition(starting_state, transition).0
+    }
+
+    /// An ATM's goal state is a successfully authenticated session.
+    fn is_accepting(state: &Self::State) -> bool {
+        state.expected_pin_hash == Auth::Authenticated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIN: &[Key] = &[Key::One, Key::Two, Key::Three, Key::Four];
+
+    /// Drive `atm` through a whole action sequence, returning the final
+    /// state and the last effect seen.
+    fn run(mut atm: Atm, actions: &[Action]) -> (Atm, Option<Effect>) {
+        let mut last_effect = None;
+        for action in actions {
+            let (next, effect) = Atm::transition(&atm, action);
+            atm = next;
+            if effect.is_some() {
+                last_effect = effect;
+            }
+        }
+        (atm, last_effect)
+    }
+
+    /// A machine that has already seen a card swipe and a correct PIN.
+    fn authenticated(cash: u64) -> Atm {
+        authenticated_from(Atm::new(cash))
+    }
+
+    /// Swipe and enter the correct PIN on an existing machine.
+    fn authenticated_from(atm: Atm) -> Atm {
+        let mut actions = vec![Action::SwipeCard(hash_pin(PIN))];
+        actions.extend(PIN.iter().map(|k| Action::PressKey(*k)));
+        actions.push(Action::PressKey(Key::Enter));
+        run(atm, &actions).0
+    }
+
+    /// Key in `amount` digit by digit and press `Enter`.
+    fn withdraw(atm: Atm, digits: &[Key]) -> (Atm, Option<Effect>) {
+        let mut actions: Vec<Action> = digits.iter().map(|k| Action::PressKey(*k)).collect();
+        actions.push(Action::PressKey(Key::Enter));
+        run(atm, &actions)
+    }
+
+    #[test]
+    fn swipe_starts_authentication() {
+        let atm = Atm::new(100);
+        let (next, effect) = Atm::transition(&atm, &Action::SwipeCard(hash_pin(PIN)));
+        assert_eq!(next.expected_pin_hash, Auth::Authenticating(hash_pin(PIN)));
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn correct_pin_authenticates() {
+        let atm = authenticated(100);
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        assert!(atm.keystroke_register.is_empty());
+    }
+
+    #[test]
+    fn authentication_works_on_an_empty_machine() {
+        // No fee is charged on authentication, so cash 0 is fine...
+        let atm = authenticated(0);
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        assert_eq!(atm.cash_inside, 0);
+        // ...but any withdrawal attempt is still refused.
+        let (atm, effect) = withdraw(atm, &[Key::One]);
+        assert_eq!(atm.cash_inside, 0);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn wrong_pin_returns_to_waiting() {
+        let atm = run(
+            Atm::new(100),
+            &[
+                Action::SwipeCard(hash_pin(PIN)),
+                Action::PressKey(Key::Nine),
+                Action::PressKey(Key::Enter),
+            ],
+        )
+        .0;
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        assert_eq!(atm.failed_attempts, 1);
+    }
+
+    #[test]
+    fn three_wrong_pins_lock_the_machine() {
+        let mut atm = Atm::new(100);
+        for _ in 0..3 {
+            atm = fail_pin_once(atm);
+        }
+        assert_eq!(atm.expected_pin_hash, Auth::Locked);
+        // A locked machine ignores further swipes.
+        let (next, _) = Atm::transition(&atm, &Action::SwipeCard(hash_pin(PIN)));
+        assert_eq!(next, atm);
+    }
+
+    /// One swipe-and-wrong-PIN round trip.
+    fn fail_pin_once(atm: Atm) -> Atm {
+        run(
+            atm,
+            &[
+                Action::SwipeCard(hash_pin(PIN)),
+                Action::PressKey(Key::Nine),
+                Action::PressKey(Key::Enter),
+            ],
+        )
+        .0
+    }
+
+    #[test]
+    fn single_attempt_machine_locks_on_first_failure() {
+        let atm = fail_pin_once(Atm::new(100).with_max_attempts(1));
+        assert_eq!(atm.expected_pin_hash, Auth::Locked);
+    }
+
+    #[test]
+    fn five_attempt_machine_locks_on_fifth_failure() {
+        let mut atm = Atm::new(100).with_max_attempts(5);
+        for _ in 0..4 {
+            atm = fail_pin_once(atm);
+            assert_ne!(atm.expected_pin_hash, Auth::Locked);
+        }
+        atm = fail_pin_once(atm);
+        assert_eq!(atm.expected_pin_hash, Auth::Locked);
+    }
+
+    #[test]
+    fn withdrawal_dispenses_and_debits() {
+        let (atm, effect) = withdraw(authenticated(100), &[Key::One, Key::Four]);
+        assert_eq!(atm.cash_inside, 86);
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        let effect = effect.expect("withdrawal should dispense");
+        assert!(matches!(effect, Effect::Dispensed { amount: 14, .. }));
+    }
+
+    #[test]
+    fn round_down_dispenses_nearest_lower_multiple_with_notice() {
+        let atm = authenticated(100)
+            .with_denominations(vec![5])
+            .with_round_down(true);
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Seven]);
+        assert_eq!(atm.cash_inside, 85);
+        assert_eq!(
+            effect,
+            Some(Effect::RoundedDown {
+                requested: 17,
+                dispensed: 15,
+            })
+        );
+    }
+
+    #[test]
+    fn without_round_down_unformable_requests_are_refused() {
+        let atm = authenticated(100).with_denominations(vec![5]);
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Seven]);
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn transaction_counter_counts_and_resets() {
+        let atm = Atm::new(100);
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::One, Key::Zero]);
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::Two, Key::Zero]);
+        assert_eq!(atm.transaction_count(), 2);
+        let atm = run(
+            atm,
+            &[
+                Action::MaintenanceKey(true),
+                Action::ResetCounters,
+                Action::MaintenanceKey(false),
+            ],
+        )
+        .0;
+        assert_eq!(atm.transaction_count(), 0);
+    }
+
+    #[test]
+    fn setting_the_clock_past_the_timeout_ends_the_session() {
+        let atm = authenticated(100).with_idle_timeout(30);
+        // One absolute jump instead of thirty ticks.
+        let atm = run(atm, &[Action::SetClock(1_000)]).0;
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+    }
+
+    #[test]
+    fn setting_the_clock_within_the_timeout_keeps_the_session() {
+        let atm = authenticated(100).with_idle_timeout(30);
+        let atm = run(atm, &[Action::SetClock(29)]).0;
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+    }
+
+    #[test]
+    fn power_loss_mid_pin_drops_safely_to_waiting() {
+        let atm = run(
+            Atm::new(100),
+            &[
+                Action::SwipeCard(hash_pin(PIN)),
+                Action::PressKey(Key::One),
+                Action::PressKey(Key::Two),
+                Action::PowerLoss,
+            ],
+        )
+        .0;
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        assert!(atm.keystroke_register.is_empty());
+        assert_eq!(atm.cash_inside, 100);
+        // A dark machine ignores customers until power returns.
+        let (next, effect) = Atm::transition(&atm, &Action::SwipeCard(hash_pin(PIN)));
+        assert_eq!(next, atm);
+        assert_eq!(effect, None);
+        let atm = run(atm, &[Action::PowerRestore]).0;
+        let atm = authenticated_from(atm);
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+    }
+
+    #[test]
+    fn lockout_survives_a_power_cycle() {
+        let mut atm = Atm::new(100);
+        for _ in 0..3 {
+            atm = fail_pin_once(atm);
+        }
+        let atm = run(atm, &[Action::PowerLoss, Action::PowerRestore]).0;
+        assert_eq!(atm.expected_pin_hash, Auth::Locked);
+    }
+
+    #[test]
+    fn waiting_and_authenticated_states_are_not_equivalent() {
+        let alphabet = [
+            Action::PressKey(Key::One),
+            Action::PressKey(Key::Enter),
+        ];
+        assert!(!states_equivalent::<Atm>(
+            &Atm::new(100),
+            &authenticated(100),
+            &alphabet,
+            3,
+        ));
+    }
+
+    #[test]
+    fn identical_machines_are_equivalent() {
+        let alphabet = [Action::PressKey(Key::One), Action::Tick];
+        assert!(states_equivalent::<Atm>(
+            &Atm::new(100),
+            &Atm::new(100),
+            &alphabet,
+            3,
+        ));
+    }
+
+    #[test]
+    fn forgotten_card_is_retained_after_the_timeout() {
+        let atm = Atm::new(100).with_idle_timeout(60);
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::One, Key::Zero]);
+        assert!(atm.card_inserted);
+        // The card sits in the reader for the whole card timeout.
+        let mut atm = atm;
+        let mut effect = None;
+        for _ in 0..Atm::DEFAULT_CARD_TIMEOUT {
+            let (next, e) = Atm::transition(&atm, &Action::Tick);
+            atm = next;
+            effect = e;
+        }
+        assert_eq!(effect, Some(Effect::CardRetained));
+        assert!(!atm.card_inserted);
+    }
+
+    #[test]
+    fn taken_card_is_not_retained() {
+        let atm = Atm::new(100).with_idle_timeout(60);
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::One, Key::Zero]);
+        let mut atm = run(atm, &[Action::TakeCard]).0;
+        for _ in 0..Atm::DEFAULT_CARD_TIMEOUT {
+            let (next, effect) = Atm::transition(&atm, &Action::Tick);
+            assert_eq!(effect, None);
+            atm = next;
+        }
+        assert!(!atm.card_inserted);
+    }
+
+    #[test]
+    fn dot_enters_cents_on_a_scaled_machine() {
+        // Cash and denominations are in cents on a scale-2 machine.
+        let atm = authenticated(1_000).with_amount_scale(2);
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Dot, Key::Five, Key::Zero]);
+        assert_eq!(atm.cash_inside, 850);
+        assert!(matches!(
+            effect,
+            Some(Effect::Dispensed { amount: 150, .. })
+        ));
+    }
+
+    #[test]
+    fn double_dot_amounts_are_rejected() {
+        let atm = authenticated(1_000).with_amount_scale(2);
+        let (atm, effect) = withdraw(
+            atm,
+            &[Key::One, Key::Dot, Key::Five, Key::Dot, Key::Zero],
+        );
+        assert_eq!(atm.cash_inside, 1_000);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn dot_is_rejected_on_whole_dollar_machines() {
+        let (atm, effect) = withdraw(authenticated(100), &[Key::One, Key::Dot, Key::Five]);
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn metrics_count_a_mixed_action_sequence() {
+        // A failed PIN attempt (5 keys), then a successful session (5 keys)
+        // withdrawing $10 (3 keys), then a refused over-limit request (4 keys).
+        let atm = fail_pin_once(Atm::new(100));
+        let atm = authenticated_from(atm);
+        let (atm, _) = withdraw(atm, &[Key::One, Key::Zero]);
+        let atm = authenticated_from(atm);
+        let (atm, _) = withdraw(atm, &[Key::Nine, Key::Nine, Key::Nine]);
+        assert_eq!(
+            atm.metrics(),
+            &Metrics {
+                swipes: 3,
+                keypresses: 2 + 5 + 3 + 5 + 4,
+                withdrawals: 1,
+                failures: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn maintenance_key_toggles_supervisor_mode() {
+        let atm = Atm::new(100);
+        assert!(!atm.is_supervisor());
+        let atm = run(atm, &[Action::MaintenanceKey(true)]).0;
+        assert!(atm.is_supervisor());
+        let atm = run(atm, &[Action::MaintenanceKey(false)]).0;
+        assert!(!atm.is_supervisor());
+    }
+
+    #[test]
+    fn customer_withdrawals_are_blocked_during_maintenance() {
+        let atm = run(authenticated(100), &[Action::MaintenanceKey(true)]).0;
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Zero]);
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(effect, None);
+        // Turning the key back off restores service.
+        let atm = run(atm, &[Action::MaintenanceKey(false)]).0;
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::One, Key::Zero]);
+        assert_eq!(atm.cash_inside, 90);
+        assert!(effect.is_some());
+    }
+
+    #[test]
+    fn reset_counters_requires_supervisor() {
+        let (atm, _) = withdraw(authenticated(100), &[Key::One, Key::Zero]);
+        let atm = run(atm, &[Action::ResetCounters]).0;
+        assert_eq!(atm.transaction_count(), 1);
+    }
+
+    #[test]
+    fn custom_salted_hasher_authenticates_end_to_end() {
+        /// Salts the digit sequence before hashing.
+        #[derive(Debug)]
+        struct SaltedHasher(u64);
+
+        impl PinHasher for SaltedHasher {
+            fn hash(&self, keys: &[Key]) -> u64 {
+                hash_pin(keys) ^ self.0
+            }
+        }
+
+        let hasher = SaltedHasher(0xdead_beef);
+        let card_hash = hasher.hash(PIN);
+        // The salted hash differs from the stock one, so a stock machine
+        // would reject this card.
+        assert_ne!(card_hash, hash_pin(PIN));
+
+        let atm = Atm::new(100).with_pin_hasher(hasher);
+        let mut actions = vec![Action::SwipeCard(card_hash)];
+        actions.extend(PIN.iter().map(|k| Action::PressKey(*k)));
+        actions.push(Action::PressKey(Key::Enter));
+        let atm = run(atm, &actions).0;
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+    }
+
+    #[test]
+    fn unformable_amount_reports_the_specific_error() {
+        let atm = Atm::new(100).with_denominations(vec![10, 5]);
+        assert_eq!(
+            atm.plan_withdrawal(13),
+            Err(AtmError::CannotMakeExactAmount { requested: 13 })
+        );
+        // An empty machine is a different failure.
+        assert_eq!(
+            Atm::new(0).with_denominations(vec![10, 5]).plan_withdrawal(13),
+            Err(AtmError::MachineOutOfCash)
+        );
+    }
+
+    #[test]
+    fn overdraw_is_rejected() {
+        let (atm, effect) = withdraw(authenticated(10), &[Key::Nine, Key::Nine]);
+        assert_eq!(atm.cash_inside, 10);
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn tap_withdrawal_under_the_tap_limit_needs_no_pin() {
+        let atm = run(
+            Atm::new(100).with_tap_limit(20),
+            &[Action::TapCard(hash_pin(PIN))],
+        )
+        .0;
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Five]);
+        assert_eq!(atm.cash_inside, 85);
+        assert!(matches!(
+            effect,
+            Some(Effect::Dispensed { amount: 15, .. })
+        ));
+    }
+
+    #[test]
+    fn tap_withdrawal_over_the_tap_limit_is_rejected() {
+        let atm = run(
+            Atm::new(100).with_tap_limit(20),
+            &[Action::TapCard(hash_pin(PIN))],
+        )
+        .0;
+        let (atm, effect) = withdraw(atm, &[Key::Two, Key::Five]);
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(effect, None);
+        // The same amount is fine for a swiped-and-PINned session.
+        let (atm, effect) = withdraw(authenticated(100), &[Key::Two, Key::Five]);
+        assert_eq!(atm.cash_inside, 75);
+        assert!(effect.is_some());
+    }
+
+    #[test]
+    fn auth_timeout_during_pin_entry_cancels_to_waiting() {
+        let (atm, effect) = run(
+            Atm::new(100),
+            &[
+                Action::SwipeCard(hash_pin(PIN)),
+                Action::PressKey(Key::One),
+                Action::PressKey(Key::Two),
+                Action::AuthTimeout,
+            ],
+        );
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        assert!(atm.keystroke_register.is_empty());
+        assert_eq!(effect, Some(Effect::NetworkError));
+    }
+
+    #[test]
+    fn auth_timeout_while_waiting_is_ignored() {
+        let atm = Atm::new(100);
+        let (next, effect) = Atm::transition(&atm, &Action::AuthTimeout);
+        assert_eq!(next, atm);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn keys_before_swipe_are_ignored() {
+        let atm = Atm::new(100);
+        let (next, _) = Atm::transition(&atm, &Action::PressKey(Key::One));
+        assert_eq!(next, atm);
+    }
+
+    #[test]
+    fn idle_session_times_out() {
+        let atm = authenticated(100).with_idle_timeout(2);
+        let atm = run(atm, &[Action::Tick, Action::Tick]).0;
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+    }
+
+    #[test]
+    fn fewest_bills_prefers_large_denominations() {
+        let atm = authenticated(100)
+            .with_denominations(vec![20, 10, 5])
+            .with_dispense_policy(DispensePolicy::FewestBills);
+        let (_, effect) = withdraw(atm, &[Key::Three, Key::Zero]);
+        assert_eq!(
+            effect,
+            Some(Effect::Dispensed {
+                amount: 30,
+                bills: vec![20, 10],
+                balance_after: 70,
+            })
+        );
+    }
+
+    #[test]
+    fn small_bills_first_drains_small_denominations() {
+        let atm = authenticated(100)
+            .with_denominations(vec![20, 10, 5])
+            .with_dispense_policy(DispensePolicy::SmallBillsFirst);
+        let (_, effect) = withdraw(atm, &[Key::Three, Key::Zero]);
+        assert_eq!(
+            effect,
+            Some(Effect::Dispensed {
+                amount: 30,
+                bills: vec![5, 5, 5, 5, 5, 5],
+                balance_after: 70,
+            })
+        );
+    }
+
+    #[test]
+    fn config_snapshot_matches_construction() {
+        let atm = Atm::new(100)
+            .with_denominations(vec![5, 50, 10])
+            .with_dispense_policy(DispensePolicy::SmallBillsFirst)
+            .with_max_attempts(5)
+            .with_tap_limit(25)
+            .with_max_withdrawal(200)
+            .with_daily_limit(400)
+            .with_idle_timeout(60);
+        assert_eq!(
+            atm.config(),
+            AtmConfig {
+                denominations: vec![50, 10, 5],
+                dispense_policy: DispensePolicy::SmallBillsFirst,
+                allow_partial: false,
+                round_down: false,
+                max_attempts: 5,
+                tap_limit: 25,
+                max_withdrawal: 200,
+                daily_limit: 400,
+                idle_timeout: 60,
+                amount_scale: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn withdrawals_remaining_divides_cash() {
+        let atm = Atm::new(100);
+        assert_eq!(atm.withdrawals_remaining(20), 5);
+        assert_eq!(atm.withdrawals_remaining(30), 3);
+    }
+
+    #[test]
+    fn withdrawals_remaining_for_zero_amount_is_zero() {
+        assert_eq!(Atm::new(100).withdrawals_remaining(0), 0);
+    }
+
+    #[test]
+    fn only_authenticated_states_accept() {
+        assert!(Atm::is_accepting(&authenticated(100)));
+        assert!(!Atm::is_accepting(&Atm::new(100)));
+        let authenticating = run(Atm::new(100), &[Action::SwipeCard(hash_pin(PIN))]).0;
+        assert!(!Atm::is_accepting(&authenticating));
+        let locked = fail_pin_once(Atm::new(100).with_max_attempts(1));
+        assert!(!Atm::is_accepting(&locked));
+    }
+
+    #[test]
+    fn debug_output_masks_pin_digits() {
+        let atm = run(
+            Atm::new(100),
+            &[
+                Action::SwipeCard(hash_pin(PIN)),
+                Action::PressKey(Key::One),
+                Action::PressKey(Key::Two),
+                Action::PressKey(Key::Three),
+            ],
+        )
+        .0;
+        let debug = format!("{atm:?}");
+        assert!(debug.contains("[*; 3]"), "register not masked: {debug}");
+        assert!(!debug.contains("One"), "PIN digit leaked: {debug}");
+
+        // Amount digits are not secret and stay visible.
+        let atm = run(
+            authenticated(100),
+            &[Action::PressKey(Key::Four), Action::PressKey(Key::Two)],
+        )
+        .0;
+        let debug = format!("{atm:?}");
+        assert!(debug.contains("Four"), "amount digits hidden: {debug}");
+    }
+
+    #[test]
+    fn overlong_amounts_are_rejected_not_wrapped() {
+        // Twenty-five nines overflow u64 by a comfortable margin.
+        let digits = vec![Key::Nine; 25];
+        let (atm, effect) = withdraw(authenticated(100), &digits);
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn inserting_three_notes_and_finalizing_deposits_their_sum() {
+        let card = hash_pin(PIN);
+        let atm = authenticated_from(Atm::new(100).with_account(card, 50));
+        let (atm, effect) = run(
+            atm,
+            &[
+                Action::InsertNote(20),
+                Action::InsertNote(10),
+                Action::InsertNote(20),
+                Action::PressKey(Key::Enter),
+            ],
+        );
+        assert_eq!(effect, Some(Effect::Deposited { amount: 50 }));
+        assert_eq!(atm.cash_inside, 150);
+        assert_eq!(atm.account_balance(card), Some(100));
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+    }
+
+    #[test]
+    fn unaccepted_notes_bounce_back() {
+        let atm = authenticated(100).with_denominations(vec![20, 10, 5]);
+        // A $7 bill does not exist; the slot rejects it.
+        let (atm, _) = run(atm, &[Action::InsertNote(7)]);
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        let (atm, effect) = run(
+            atm,
+            &[Action::InsertNote(20), Action::InsertNote(7), Action::PressKey(Key::Enter)],
+        );
+        assert_eq!(effect, Some(Effect::Deposited { amount: 20 }));
+        assert_eq!(atm.cash_inside, 120);
+    }
+
+    #[test]
+    fn notes_are_only_accepted_mid_session() {
+        let atm = Atm::new(100);
+        let (next, effect) = Atm::transition(&atm, &Action::InsertNote(20));
+        assert_eq!(next, atm);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn prompt_follows_the_state() {
+        let atm = Atm::new(100);
+        assert_eq!(atm.prompt(), "Please swipe your card");
+        let atm = run(atm, &[Action::SwipeCard(hash_pin(PIN))]).0;
+        assert_eq!(atm.prompt(), "Enter PIN");
+        let atm = authenticated(100);
+        assert_eq!(atm.prompt(), "Enter amount");
+        let atm = run(atm, &[Action::PowerLoss]).0;
+        assert_eq!(atm.prompt(), "Out of service");
+    }
+
+    #[test]
+    fn withdrawal_needs_both_account_funds_and_machine_cash() {
+        let card = hash_pin(PIN);
+        // Rich account, empty machine: the machine side fails.
+        let atm = authenticated_from(Atm::new(5).with_account(card, 1_000));
+        assert_eq!(
+            atm.preview_withdrawal(10),
+            Err(AtmError::MachineOutOfCash)
+        );
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Zero]);
+        assert_eq!(atm.cash_inside, 5);
+        assert_eq!(atm.account_balance(card), Some(1_000));
+        assert_eq!(effect, None);
+
+        // Rich machine, poor account: the account side fails.
+        let atm = authenticated_from(Atm::new(100).with_account(card, 5));
+        assert_eq!(
+            atm.preview_withdrawal(10),
+            Err(AtmError::InsufficientAccountFunds)
+        );
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Zero]);
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(atm.account_balance(card), Some(5));
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn successful_withdrawal_debits_account_and_machine() {
+        let card = hash_pin(PIN);
+        let atm = authenticated_from(Atm::new(100).with_account(card, 40));
+        let (atm, effect) = withdraw(atm, &[Key::Three, Key::Zero]);
+        assert_eq!(atm.cash_inside, 70);
+        assert_eq!(atm.account_balance(card), Some(10));
+        assert!(effect.is_some());
+    }
+
+    #[test]
+    fn blocked_card_is_rejected_before_authenticating() {
+        let card = hash_pin(PIN);
+        let atm = run(
+            Atm::new(100),
+            &[
+                Action::MaintenanceKey(true),
+                Action::BlockCard(card),
+                Action::MaintenanceKey(false),
+            ],
+        )
+        .0;
+        let (atm, effect) = Atm::transition(&atm, &Action::SwipeCard(card));
+        assert_eq!(atm.expected_pin_hash, Auth::CardRejected);
+        assert_eq!(effect, Some(Effect::CardBlocked));
+        // A different, unlisted card is accepted straight away.
+        let (atm, effect) = Atm::transition(&atm, &Action::SwipeCard(card + 1));
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticating(card + 1));
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn blocking_a_card_requires_supervisor() {
+        let card = hash_pin(PIN);
+        let atm = run(Atm::new(100), &[Action::BlockCard(card)]).0;
+        let (atm, effect) = Atm::transition(&atm, &Action::SwipeCard(card));
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticating(card));
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn preview_leaves_the_original_untouched() {
+        let atm = authenticated(100);
+        assert_eq!(atm.preview_withdrawal(30), Ok(70));
+        let previewed = atm.preview(&Action::PressKey(Key::One));
+        assert_eq!(previewed.keystroke_register, vec![Key::One]);
+        // The original is unchanged by either preview.
+        assert_eq!(atm.cash_inside, 100);
+        assert!(atm.keystroke_register.is_empty());
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+    }
+
+    #[test]
+    fn preview_withdrawal_reports_failures() {
+        assert_eq!(
+            authenticated(10).preview_withdrawal(30),
+            Err(AtmError::MachineOutOfCash)
+        );
+    }
+
+    #[test]
+    fn withdrawal_keys_spell_out_the_amount() {
+        assert_eq!(
+            withdrawal_keys(140),
+            vec![Key::One, Key::Four, Key::Zero, Key::Enter]
+        );
+        assert_eq!(withdrawal_keys(7), vec![Key::Seven, Key::Enter]);
+    }
+
+    #[test]
+    fn withdrawal_keys_round_trip_through_the_machine() {
+        let actions: Vec<Action> = withdrawal_keys(30)
+            .into_iter()
+            .map(Action::PressKey)
+            .collect();
+        let (atm, effect) = run(authenticated(100), &actions);
+        assert_eq!(atm.cash_inside, 70);
+        assert!(effect.is_some());
+    }
+
+    #[test]
+    fn key_all_lists_every_variant() {
+        let keys = Key::all();
+        // Ten digits, the decimal dot, and Enter.
+        assert_eq!(keys.len(), 12);
+        // No duplicates: every variant appears exactly once.
+        for (i, a) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..].contains(a), "{a:?} listed twice");
+        }
+    }
+
+    #[test]
+    fn receipt_shows_amount_and_new_balance() {
+        let (_, effect) = withdraw(authenticated(100), &[Key::One, Key::Four]);
+        let text = effect.expect("withdrawal should dispense").to_string();
+        assert!(text.contains("$14"), "no amount in receipt: {text}");
+        assert!(text.contains("$86"), "no balance in receipt: {text}");
+    }
+
+    #[test]
+    fn spanish_withdrawal_message() {
+        let atm = run(
+            authenticated(100),
+            &[Action::SetLanguage(Language::Spanish)],
+        )
+        .0;
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Four]);
+        let effect = effect.expect("withdrawal should dispense");
+        assert_eq!(
+            atm.message(&effect),
+            "Por favor retire sus $14. Nuevo saldo: $86"
+        );
+        // `Display` stays English regardless of the machine's language.
+        assert_eq!(
+            effect.to_string(),
+            "Please take your $14. New balance: $86"
+        );
+    }
+
+    #[test]
+    fn default_policy_is_fewest_bills() {
+        assert_eq!(DispensePolicy::default(), DispensePolicy::FewestBills);
+    }
+
+    #[cfg(feature = "debug-insights")]
+    #[test]
+    fn pin_distance_counts_differing_positions() {
+        let atm = run(Atm::new(100), &[Action::SwipeCard(hash_pin(PIN))]).0;
+        // [1, 2, 3, 5] differs from the 1234 PIN in the last key only.
+        assert_eq!(
+            atm.pin_distance(&[Key::One, Key::Two, Key::Three, Key::Five]),
+            Some(1)
+        );
+        assert_eq!(atm.pin_distance(PIN), Some(0));
+        // Without a card in session there is nothing to compare against.
+        assert_eq!(Atm::new(100).pin_distance(PIN), None);
+    }
+
+    #[test]
+    fn the_reserve_refuses_the_last_dollars() {
+        let atm = authenticated(20).with_min_reserve(5);
+        // $20 would empty the machine; the reserve refuses it.
+        assert_eq!(atm.preview_withdrawal(20), Err(AtmError::BelowReserve));
+        let (atm, effect) = withdraw(atm, &[Key::Two, Key::Zero]);
+        assert_eq!(effect, None);
+        assert_eq!(atm.cash_inside, 20);
+        // $15 leaves exactly the float and goes through.
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::One, Key::Five]);
+        assert!(effect.is_some());
+        assert_eq!(atm.cash_inside, 5);
+        // Without a reserve the machine will hand over its last dollar.
+        let (atm, effect) = withdraw(authenticated(20), &[Key::Two, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.cash_inside, 0);
+    }
+
+    #[test]
+    fn the_machine_identifies_itself() {
+        let atm = Atm::new(100).with_id("lobby-03");
+        assert_eq!(atm.id(), "lobby-03");
+        let (next, effect) = Atm::transition(&atm, &Action::Identify);
+        assert_eq!(effect, Some(Effect::MachineId("lobby-03".to_string())));
+        assert_eq!(next, atm);
+    }
+
+    #[test]
+    fn stats_summarize_a_known_history() {
+        // One failed PIN, a $30 and a $10 withdrawal, and a $20 deposit.
+        let atm = fail_pin_once(Atm::new(200));
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::Three, Key::Zero]);
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::One, Key::Zero]);
+        let atm = run(
+            authenticated_from(atm),
+            &[Action::InsertNote(20), Action::PressKey(Key::Enter)],
+        )
+        .0;
+        assert_eq!(
+            atm.stats(),
+            HistoryStats {
+                total_withdrawn: 40,
+                total_deposited: 20,
+                failed_pins: 1,
+                average_withdrawal: 20,
+            }
+        );
+        // A machine that has seen nothing reports zeroes throughout.
+        assert_eq!(Atm::new(100).stats(), HistoryStats::default());
+    }
+
+    #[test]
+    fn pulling_the_chip_card_mid_deposit_returns_the_notes() {
+        let atm = run(
+            Atm::new(100),
+            &[
+                Action::InsertChipCard(hash_pin(PIN)),
+                Action::EnterPin(PIN.to_vec()),
+                Action::InsertNote(10),
+                Action::InsertNote(20),
+            ],
+        )
+        .0;
+        let (atm, effect) = Atm::transition(&atm, &Action::RemoveCard);
+        assert_eq!(effect, Some(Effect::ReturnNotes(vec![10, 20])));
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        // Nothing was credited and nothing vanished.
+        assert_eq!(atm.cash_inside, 100);
+    }
+
+    #[test]
+    fn chip_sessions_hold_until_the_card_is_removed() {
+        let atm = run(
+            Atm::new(100),
+            &[
+                Action::InsertChipCard(hash_pin(PIN)),
+                Action::EnterPin(PIN.to_vec()),
+            ],
+        )
+        .0;
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        // A withdrawal does not end a chip session...
+        let (atm, effect) = withdraw(atm, &[Key::Three, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        // ...a second one runs straight away...
+        let (atm, effect) = withdraw(atm, &[Key::Two, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.cash_inside, 50);
+        // ...and removing the card closes it.
+        let (atm, effect) = Atm::transition(&atm, &Action::RemoveCard);
+        assert_eq!(effect, None);
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        let (_, status) = Atm::transition(&atm, &Action::CardStatus);
+        assert_eq!(status, Some(Effect::CardPresent(false)));
+    }
+
+    #[test]
+    fn pin_attempts_back_off_after_failures_and_recover() {
+        let atm = Atm::new(100);
+        assert!(atm.can_attempt_pin());
+        // One failure opens a 5-second advisory cooldown.
+        let mut atm = fail_pin_once(atm);
+        assert!(!atm.can_attempt_pin());
+        for _ in 0..Atm::DEFAULT_ATTEMPT_BACKOFF {
+            atm = Atm::transition(&atm, &Action::Tick).0;
+        }
+        assert!(atm.can_attempt_pin());
+        // A second failure doubles the wait.
+        let mut atm = fail_pin_once(atm);
+        for _ in 0..Atm::DEFAULT_ATTEMPT_BACKOFF {
+            atm = Atm::transition(&atm, &Action::Tick).0;
+        }
+        assert!(!atm.can_attempt_pin());
+        for _ in 0..Atm::DEFAULT_ATTEMPT_BACKOFF {
+            atm = Atm::transition(&atm, &Action::Tick).0;
+        }
+        assert!(atm.can_attempt_pin());
+        // A locked machine takes no attempts at all, however long ago.
+        let locked = fail_pin_once(Atm::new(100).with_max_attempts(1));
+        let locked = run(locked, &[Action::SetClock(1_000_000)]).0;
+        assert!(!locked.can_attempt_pin());
+    }
+
+    #[test]
+    fn account_tiers_have_their_own_withdrawal_ceilings() {
+        let basic = hash_pin(PIN);
+        let premium = hash_keys(&[Key::Five, Key::Six, Key::Seven, Key::Eight]);
+        let atm = Atm::new(5_000)
+            .with_daily_limit(5_000)
+            .with_account_limit(basic, 50)
+            .with_account_limit(premium, 1_000);
+        // The basic card is capped at its tier...
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Six, Key::Zero]);
+        assert_eq!(effect, None);
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Five, Key::Zero]);
+        assert!(effect.is_some());
+        // ...while the premium card sails past the $500 machine default.
+        let mut atm = atm;
+        for action in [
+            Action::SwipeCard(premium),
+            Action::EnterPin(vec![Key::Five, Key::Six, Key::Seven, Key::Eight]),
+        ] {
+            atm = Atm::transition(&atm, &action).0;
+        }
+        let (atm, effect) = withdraw(atm, &[Key::Eight, Key::Zero, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.cash_inside, 5_000 - 50 - 800);
+        // Cards without a tier still get the machine-wide maximum.
+        let (_, effect) = withdraw(
+            authenticated(5_000).with_daily_limit(5_000),
+            &[Key::Six, Key::Zero, Key::Zero],
+        );
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn a_confirming_partial_swipe_completes_the_read() {
+        let card = hash_pin(PIN);
+        let (atm, effect) = Atm::transition(&Atm::new(100), &Action::PartialSwipe(card));
+        assert_eq!(effect, Some(Effect::ReswipeNeeded));
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        // The matching second read inside the window reads as a swipe.
+        let (atm, effect) = Atm::transition(&atm, &Action::PartialSwipe(card));
+        assert_eq!(effect, None);
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticating(card));
+    }
+
+    #[test]
+    fn a_partial_swipe_lapses_after_the_window() {
+        let card = hash_pin(PIN);
+        let mut atm = Atm::transition(&Atm::new(100), &Action::PartialSwipe(card)).0;
+        for _ in 0..=Atm::DEFAULT_RESWIPE_WINDOW {
+            atm = Atm::transition(&atm, &Action::Tick).0;
+        }
+        // Too late: the second read starts over instead of confirming.
+        let (atm, effect) = Atm::transition(&atm, &Action::PartialSwipe(card));
+        assert_eq!(effect, Some(Effect::ReswipeNeeded));
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+    }
+
+    #[test]
+    fn a_draining_dispense_also_warns_of_low_cash() {
+        // 600 cash against the default $500 limit: one withdrawal leaves
+        // the machine unable to cover another maximum withdrawal.
+        let atm = authenticated(600);
+        let mut atm = run(atm, &[Action::PressKey(Key::Two), Action::PressKey(Key::Zero)]).0;
+        atm = run(atm, &[Action::PressKey(Key::Zero)]).0;
+        let (next, effects) = Atm::next_state_with_effects(&atm, &Action::PressKey(Key::Enter));
+        assert_eq!(effects.len(), 2);
+        assert!(matches!(effects[0], Effect::Dispensed { amount: 200, .. }));
+        assert_eq!(effects[1], Effect::LowCash { remaining: 400 });
+        assert_eq!(next.cash_inside, 400);
+        // A dispense that leaves plenty behind carries no warning.
+        let (_, effects) = {
+            let atm = authenticated(10_000);
+            let atm = run(
+                atm,
+                &[Action::PressKey(Key::Two), Action::PressKey(Key::Zero)],
+            )
+            .0;
+            Atm::next_state_with_effects(&atm, &Action::PressKey(Key::Enter))
+        };
+        assert_eq!(effects.len(), 1);
+    }
+
+    #[test]
+    fn lifetime_dispensed_accumulates_across_resets() {
+        let (atm, _) = withdraw(authenticated(100), &[Key::Three, Key::Zero]);
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::Two, Key::Zero]);
+        assert_eq!(atm.lifetime_dispensed(), 50);
+        // Neither midnight nor a counters reset touches it.
+        let atm = run(
+            atm,
+            &[
+                Action::NewDay,
+                Action::MaintenanceKey(true),
+                Action::ResetCounters,
+                Action::MaintenanceKey(false),
+            ],
+        )
+        .0;
+        assert_eq!(atm.lifetime_dispensed(), 50);
+        assert_eq!(atm.transaction_count(), 0);
+        assert_eq!(atm.withdrawn_today, 0);
+    }
+
+    #[test]
+    fn declining_receipts_silences_transaction_effects() {
+        let atm = run(
+            authenticated(100),
+            &[Action::SetReceiptPreference(false)],
+        )
+        .0;
+        // The cash still moves, but no receipt effect comes out and
+        // there is nothing to reprint.
+        let (atm, effect) = withdraw(atm, &[Key::Three, Key::Zero]);
+        assert_eq!(effect, None);
+        assert_eq!(atm.cash_inside, 70);
+        let (_, effect) = Atm::transition(&atm, &Action::ReprintReceipt);
+        assert_eq!(effect, None);
+        // The preference is per session: the next customer gets receipts.
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::One, Key::Zero]);
+        assert!(matches!(effect, Some(Effect::Dispensed { amount: 10, .. })));
+        assert_eq!(atm.cash_inside, 60);
+    }
+
+    #[test]
+    fn leading_zeros_parse_and_lone_zero_is_refused() {
+        // "020" keys in as twenty dollars.
+        let (atm, effect) = withdraw(
+            authenticated(100),
+            &[Key::Zero, Key::Two, Key::Zero],
+        );
+        assert!(matches!(effect, Some(Effect::Dispensed { amount: 20, .. })));
+        assert_eq!(atm.cash_inside, 80);
+        // A lone "0" is an invalid amount, not a zero-dollar dispense.
+        let (atm, effect) = withdraw(authenticated(100), &[Key::Zero]);
+        assert_eq!(effect, None);
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(atm.metrics().failures, 1);
+    }
+
+    #[test]
+    fn verify_log_replays_to_the_expected_state() {
+        let mut log = vec![Action::SwipeCard(hash_pin(PIN))];
+        log.push(Action::EnterPin(PIN.to_vec()));
+        log.extend([Key::Three, Key::Zero, Key::Enter].map(Action::PressKey));
+        let (expected, _) = withdraw(authenticated(100), &[Key::Three, Key::Zero]);
+        assert!(verify_log(&Atm::new(100), &log, &expected));
+        // A doctored expectation does not verify.
+        assert!(!verify_log(&Atm::new(100), &log, &Atm::new(70)));
+        assert!(!verify_log(&Atm::new(100), &log[..log.len() - 1], &expected));
+    }
+
+    #[test]
+    fn transfers_move_money_between_accounts() {
+        let card = hash_pin(PIN);
+        let friend = card + 1;
+        let atm = Atm::new(100)
+            .with_account(card, 80)
+            .with_account(friend, 10);
+        let atm = authenticated_from(atm);
+        let (atm, effect) = Atm::transition(
+            &atm,
+            &Action::Transfer {
+                to: friend,
+                amount: 30,
+            },
+        );
+        assert_eq!(
+            effect,
+            Some(Effect::Transferred {
+                to: friend,
+                amount: 30
+            })
+        );
+        assert_eq!(atm.account_balance(card), Some(50));
+        assert_eq!(atm.account_balance(friend), Some(40));
+        // No cash moved and the session stays open.
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        // A transfer to an account the machine does not know is refused.
+        let (atm, effect) = Atm::transition(
+            &atm,
+            &Action::Transfer {
+                to: 999,
+                amount: 10,
+            },
+        );
+        assert_eq!(effect, None);
+        assert_eq!(atm.account_balance(card), Some(50));
+        // So is one the source cannot cover.
+        let (atm, effect) = Atm::transition(
+            &atm,
+            &Action::Transfer {
+                to: friend,
+                amount: 500,
+            },
+        );
+        assert_eq!(effect, None);
+        assert_eq!(atm.account_balance(friend), Some(40));
+        // A card with no account of its own cannot conjure money.
+        let stranger = authenticated(100).with_account(7, 10);
+        let (stranger, effect) = Atm::transition(
+            &stranger,
+            &Action::Transfer { to: 7, amount: 50 },
+        );
+        assert_eq!(effect, None);
+        assert_eq!(stranger.account_balance(7), Some(10));
+    }
+
+    #[test]
+    fn snapshots_round_trip_through_serde() {
+        let atm = authenticated(250).with_daily_limit(300);
+        let bytes = bincode::serialize(&atm.to_snapshot()).expect("snapshot serializes");
+        let restored: Snapshot = bincode::deserialize(&bytes).expect("snapshot deserializes");
+        assert_eq!(Atm::from_snapshot(restored), atm);
+    }
+
+    #[test]
+    fn v1_snapshots_migrate_forward() {
+        let migrated = Atm::from_snapshot(Snapshot::V1 {
+            cash_inside: 80,
+            failed_attempts: 2,
+        });
+        assert_eq!(migrated.cash_inside, 80);
+        assert_eq!(migrated.failed_attempts, 2);
+        assert_eq!(migrated.expected_pin_hash, Auth::Waiting);
+        // A counter already at the limit comes back locked.
+        let locked = Atm::from_snapshot(Snapshot::V1 {
+            cash_inside: 80,
+            failed_attempts: Atm::DEFAULT_MAX_ATTEMPTS,
+        });
+        assert_eq!(locked.expected_pin_hash, Auth::Locked);
+    }
+
+    #[test]
+    fn a_stuck_key_registers_alongside_every_press() {
+        let atm = run(authenticated(100), &[Action::StuckKey(Key::Zero)]).0;
+        // Pressing 5 lands "5" and then the stuck "0".
+        let atm = Atm::transition(&atm, &Action::PressKey(Key::Five)).0;
+        assert_eq!(atm.keystroke_register, vec![Key::Five, Key::Zero]);
+        // Enter still fires — followed by the stuck 0 in the new session.
+        let (atm, effect) = Atm::transition(&atm, &Action::PressKey(Key::Enter));
+        assert!(matches!(effect, Some(Effect::Dispensed { amount: 50, .. })));
+        assert_eq!(atm.cash_inside, 50);
+        // Freed, the keypad behaves again.
+        let atm = run(
+            atm,
+            &[Action::UnstickKey, Action::SwipeCard(hash_pin(PIN))],
+        )
+        .0;
+        let atm = Atm::transition(&atm, &Action::PressKey(Key::One)).0;
+        assert_eq!(atm.keystroke_register, vec![Key::One]);
+    }
+
+    #[test]
+    fn total_cash_sums_the_bill_inventory() {
+        let atm = Atm::with_inventory(HashMap::from([(20, 5), (10, 10), (5, 3)]));
+        assert_eq!(atm.total_cash(), 215);
+        assert_eq!(atm.total_cash(), atm.cash_inside);
+        // Dispensing keeps the two figures in step.
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::Three, Key::Five]);
+        assert_eq!(atm.total_cash(), 180);
+        assert_eq!(atm.total_cash(), atm.cash_inside);
+        // A flat-cash machine answers its scalar.
+        assert_eq!(Atm::new(100).total_cash(), 100);
+        // Pathological inventories saturate instead of wrapping.
+        assert_eq!(
+            Atm::with_inventory(HashMap::from([(u64::MAX, 3)])).total_cash(),
+            u64::MAX
+        );
+    }
+
+    #[test]
+    fn expired_cards_are_refused_and_valid_ones_pass() {
+        let atm = run(Atm::new(100), &[Action::SetClock(1_000)]).0;
+        let (atm, effect) = Atm::transition(
+            &atm,
+            &Action::SwipeCardFull {
+                number: hash_pin(PIN),
+                expiry: 500,
+            },
+        );
+        assert_eq!(effect, Some(Effect::CardExpired));
+        assert_eq!(atm.expected_pin_hash, Auth::CardRejected);
+        // A card still in date swipes through to PIN entry as usual.
+        let (atm, effect) = Atm::transition(
+            &atm,
+            &Action::SwipeCardFull {
+                number: hash_pin(PIN),
+                expiry: 2_000,
+            },
+        );
+        assert_eq!(effect, None);
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticating(hash_pin(PIN)));
+    }
+
+    #[test]
+    fn two_machines_share_one_ledger() {
+        let ledger = SharedLedger::new();
+        let card = hash_pin(PIN);
+        ledger.open_account(card, 100);
+        let first = Atm::new(1_000).with_ledger(ledger.clone());
+        let second = Atm::new(1_000).with_ledger(ledger.clone());
+
+        let (_, effect) = withdraw(authenticated_from(first), &[Key::Six, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(ledger.balance(card), Some(40));
+
+        // The second machine sees the decremented balance immediately.
+        let (second, effect) = withdraw(authenticated_from(second), &[Key::Six, Key::Zero]);
+        assert_eq!(effect, None);
+        let (_, effect) = withdraw(authenticated_from(second), &[Key::Four, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(ledger.balance(card), Some(0));
+    }
+
+    #[test]
+    fn ignored_actions_leave_a_readable_reason() {
+        // A key before any swipe.
+        let atm = run(Atm::new(100), &[Action::PressKey(Key::One)]).0;
+        assert_eq!(atm.last_ignore_reason(), Some(IgnoreReason::KeyBeforeSwipe));
+        // An accepted action clears the trace.
+        let atm = run(atm, &[Action::SwipeCard(hash_pin(PIN))]).0;
+        assert_eq!(atm.last_ignore_reason(), None);
+        // Supervisor-only requests from customers say so.
+        let atm = run(Atm::new(100), &[Action::BlockCard(9)]).0;
+        assert_eq!(
+            atm.last_ignore_reason(),
+            Some(IgnoreReason::SupervisorRequired)
+        );
+        // A note fed in outside a session.
+        let atm = run(Atm::new(100), &[Action::InsertNote(10)]).0;
+        assert_eq!(
+            atm.last_ignore_reason(),
+            Some(IgnoreReason::DepositWhileUnauthenticated)
+        );
+        // Keys on a locked machine.
+        let locked = fail_pin_once(Atm::new(100).with_max_attempts(1));
+        let atm = run(locked, &[Action::PressKey(Key::One)]).0;
+        assert_eq!(atm.last_ignore_reason(), Some(IgnoreReason::MachineLocked));
+        // The in-place path records the same trace.
+        let mut atm = Atm::new(100);
+        atm.advance(&Action::PressKey(Key::One));
+        assert_eq!(atm.last_ignore_reason(), Some(IgnoreReason::KeyBeforeSwipe));
+    }
+
+    #[test]
+    fn training_mode_dispenses_effects_but_no_cash() {
+        let atm = run(
+            Atm::new(100),
+            &[
+                Action::MaintenanceKey(true),
+                Action::SetTrainingMode(true),
+                Action::MaintenanceKey(false),
+            ],
+        )
+        .0;
+        // A training withdrawal looks completely real on screen...
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Three, Key::Zero]);
+        assert!(matches!(effect, Some(Effect::Dispensed { amount: 30, .. })));
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        // ...but the drawer never opened.
+        assert_eq!(atm.cash_inside, 100);
+        // Deposits are simulated too.
+        let atm = run(
+            authenticated_from(atm),
+            &[Action::InsertNote(10), Action::PressKey(Key::Enter)],
+        )
+        .0;
+        assert_eq!(atm.cash_inside, 100);
+        // Back in live mode real cash moves again.
+        let atm = run(
+            atm,
+            &[
+                Action::MaintenanceKey(true),
+                Action::SetTrainingMode(false),
+                Action::MaintenanceKey(false),
+            ],
+        )
+        .0;
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::Three, Key::Zero]);
+        assert_eq!(atm.cash_inside, 70);
+    }
+
+    #[test]
+    fn logout_countdown_follows_the_idle_clock() {
+        assert_eq!(Atm::new(100).seconds_until_logout(), None);
+        let mut atm = authenticated(100).with_idle_timeout(30);
+        assert_eq!(atm.seconds_until_logout(), Some(30));
+        for _ in 0..12 {
+            atm = Atm::transition(&atm, &Action::Tick).0;
+        }
+        assert_eq!(atm.seconds_until_logout(), Some(18));
+        // A keypress resets the countdown.
+        let atm = Atm::transition(&atm, &Action::PressKey(Key::One)).0;
+        assert_eq!(atm.seconds_until_logout(), Some(30));
+    }
+
+    #[test]
+    fn supervisor_clears_attempts_and_unlocks() {
+        let locked = fail_pin_once(Atm::new(100).with_max_attempts(1));
+        assert_eq!(locked.expected_pin_hash, Auth::Locked);
+        // Customers cannot clear the counter...
+        let atm = run(locked, &[Action::ClearFailedAttempts]).0;
+        assert_eq!(atm.expected_pin_hash, Auth::Locked);
+        // ...an operator can, and service resumes.
+        let atm = run(
+            atm,
+            &[
+                Action::MaintenanceKey(true),
+                Action::ClearFailedAttempts,
+                Action::MaintenanceKey(false),
+            ],
+        )
+        .0;
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        assert_eq!(atm.failed_attempts, 0);
+        let atm = authenticated_from(atm);
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+    }
+
+    #[test]
+    fn single_transaction_mode_logs_out_after_dispensing() {
+        let (atm, effect) = withdraw(authenticated(100), &[Key::One, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        // Without re-authenticating, a second withdrawal goes nowhere.
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Zero]);
+        assert_eq!(effect, None);
+        assert_eq!(atm.cash_inside, 90);
+    }
+
+    #[test]
+    fn multi_transaction_mode_keeps_the_session_open() {
+        let atm = authenticated_from(Atm::new(100).with_single_transaction(false));
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        // A second withdrawal runs in the same session.
+        let (atm, effect) = withdraw(atm, &[Key::Two, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.cash_inside, 70);
+    }
+
+    #[test]
+    fn public_view_carries_no_hash() {
+        // A recognisable hash that would be easy to spot if leaked.
+        let card = 0xDEAD_BEEF_u64;
+        let atm = run(
+            Atm::new(100),
+            &[
+                Action::SwipeCard(card),
+                Action::PressKey(Key::One),
+                Action::PressKey(Key::Two),
+            ],
+        )
+        .0;
+        let view = atm.public_view();
+        assert_eq!(view.auth_kind, "authenticating");
+        assert_eq!(view.pending_keys, 2);
+        assert_eq!(view.cash_inside, 100);
+        assert_eq!(view.prompt, "Enter PIN");
+        // Neither debug nor serde output mentions the hash.
+        let serialized = bincode::serialize(&view).expect("view serializes");
+        assert!(!serialized
+            .windows(8)
+            .any(|window| window == card.to_le_bytes()));
+        assert!(!format!("{view:?}").contains("3735928559"));
+    }
+
+    #[test]
+    fn selected_account_directs_the_debit() {
+        let card = hash_pin(PIN);
+        let atm = Atm::new(500)
+            .with_account(card, 100)
+            .with_savings_account(card, 200);
+        // Sessions start on checking.
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::One, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.account_balance(card), Some(90));
+        assert_eq!(atm.savings_balance(card), Some(200));
+        // Selecting savings redirects the debit.
+        let atm = run(
+            authenticated_from(atm),
+            &[Action::SelectAccount(AccountType::Savings)],
+        )
+        .0;
+        let (atm, effect) = withdraw(atm, &[Key::Two, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.account_balance(card), Some(90));
+        assert_eq!(atm.savings_balance(card), Some(180));
+        // The next session is back on checking.
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::One, Key::Zero]);
+        assert_eq!(atm.account_balance(card), Some(80));
+        assert_eq!(atm.savings_balance(card), Some(180));
+    }
+
+    #[test]
+    fn savings_funds_bound_savings_withdrawals() {
+        let card = hash_pin(PIN);
+        let atm = Atm::new(500)
+            .with_account(card, 1_000)
+            .with_savings_account(card, 5);
+        let atm = run(
+            authenticated_from(atm),
+            &[Action::SelectAccount(AccountType::Savings)],
+        )
+        .0;
+        assert_eq!(
+            atm.preview_withdrawal(10),
+            Err(AtmError::InsufficientAccountFunds)
+        );
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Zero]);
+        assert_eq!(effect, None);
+        assert_eq!(atm.savings_balance(card), Some(5));
+    }
+
+    #[test]
+    fn pin_policies_catch_weak_pins() {
+        let strict = PinPolicy {
+            min_length: 4,
+            forbid_repeated: true,
+            forbid_sequential: true,
+        };
+        assert_eq!(
+            validate_pin(&[Key::One, Key::Two, Key::Three], &strict),
+            Err(PinError::TooShort { minimum: 4 })
+        );
+        assert_eq!(
+            validate_pin(&[Key::One; 4], &strict),
+            Err(PinError::RepeatedDigits)
+        );
+        // The crate's favourite PIN fails a no-sequential policy, both
+        // ways round.
+        assert_eq!(validate_pin(PIN, &strict), Err(PinError::SequentialDigits));
+        assert_eq!(
+            validate_pin(&[Key::Four, Key::Three, Key::Two, Key::One], &strict),
+            Err(PinError::SequentialDigits)
+        );
+        assert_eq!(
+            validate_pin(&[Key::Two, Key::Seven, Key::Four, Key::Nine], &strict),
+            Ok(())
+        );
+        assert_eq!(
+            validate_pin(&[Key::One, Key::Dot, Key::Two, Key::Three], &strict),
+            Err(PinError::NotADigit)
+        );
+        // The default policy keeps 1234 usable.
+        assert_eq!(validate_pin(PIN, &PinPolicy::default()), Ok(()));
+    }
+
+    #[test]
+    fn money_arithmetic_is_checked() {
+        assert_eq!(Money(70).checked_add(Money(30)), Some(Money(100)));
+        assert_eq!(Money(20).checked_sub(Money(30)), None);
+        assert_eq!(Money(u64::MAX).checked_add(Money(1)), None);
+        assert_eq!(Money(5).checked_mul(4), Some(Money(20)));
+    }
+
+    #[test]
+    fn absurd_amount_scales_refuse_instead_of_panicking() {
+        // 10^20 does not fit in u64; every keyed amount is simply
+        // unrepresentable, not a crash.
+        let atm = authenticated(100).with_amount_scale(20);
+        let (atm, effect) = withdraw(atm, &[Key::One]);
+        assert_eq!(effect, None);
+        assert_eq!(atm.cash_inside, 100);
+        // Display falls back to plain minor units at such scales.
+        assert_eq!(Money(150).display(30), "$150");
+    }
+
+    #[test]
+    fn money_formats_with_symbol_and_scale() {
+        assert_eq!(Money(150).to_string(), "$150");
+        assert_eq!(Money(150).display(0), "$150");
+        // At scale 2 the minor units are cents.
+        assert_eq!(Money(150).display(2), "$1.50");
+        assert_eq!(Money(105).display(2), "$1.05");
+        assert_eq!(Atm::new(250).cash().display(0), "$250");
+    }
+
+    #[test]
+    fn the_last_receipt_reprints_within_the_window() {
+        let (atm, effect) = withdraw(authenticated(100), &[Key::Three, Key::Zero]);
+        let printed = effect.expect("withdrawal should dispense").to_string();
+        // Back at Waiting, the reprint repeats the receipt verbatim.
+        let (atm, effect) = Atm::transition(&atm, &Action::ReprintReceipt);
+        assert_eq!(effect, Some(Effect::ReceiptReprinted(printed.clone())));
+        assert_eq!(atm.message(&effect.unwrap()), printed);
+        // Once the window has passed the printer has moved on.
+        let mut late = atm.clone();
+        for _ in 0..=Atm::DEFAULT_RECEIPT_WINDOW {
+            late = Atm::transition(&late, &Action::Tick).0;
+        }
+        let (_, effect) = Atm::transition(&late, &Action::ReprintReceipt);
+        assert_eq!(effect, None);
+        // A machine that never printed has nothing to reprint.
+        let (_, effect) = Atm::transition(&Atm::new(100), &Action::ReprintReceipt);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn dispensability_tracks_cash_bills_and_jams() {
+        assert!(!Atm::new(0).is_dispensable());
+        assert!(Atm::new(100).is_dispensable());
+        // Cash below the smallest bill cannot come out.
+        assert!(!Atm::new(3).with_denominations(vec![5]).is_dispensable());
+        // A hold can commit all the cash.
+        let atm = run(authenticated(100), &[Action::HoldFunds(100)]).0;
+        assert!(!atm.is_dispensable());
+        // Draining the only stocked bill empties the drawer.
+        let atm = Atm::with_inventory(HashMap::from([(20, 1)]));
+        assert!(atm.is_dispensable());
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::Two, Key::Zero]);
+        assert!(!atm.is_dispensable());
+        // A jam takes the dispenser out of service outright.
+        assert!(!run(Atm::new(100), &[Action::JamDispenser]).0.is_dispensable());
+    }
+
+    #[test]
+    fn positions_map_through_the_identity_layout_by_default() {
+        let atm = run(
+            Atm::new(100),
+            &[Action::SwipeCard(hash_pin(PIN)), Action::PressPosition(1)],
+        )
+        .0;
+        assert_eq!(atm.current_attempt(), Some(&[Key::One][..]));
+        // Out-of-range positions touch nothing.
+        let (next, effect) = Atm::transition(&atm, &Action::PressPosition(12));
+        assert_eq!(next, atm);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn seeded_shuffle_scrambles_positions_per_session() {
+        let fresh = || run(Atm::new(100).with_keypad_shuffle(7), &[Action::SwipeCard(hash_pin(PIN))]).0;
+        let atm = fresh();
+        // The deal is deterministic per seed, and is a real permutation.
+        assert_eq!(atm.keypad_layout(), fresh().keypad_layout());
+        let mut dealt = atm.keypad_layout().to_vec();
+        dealt.sort_by_key(|key| format!("{key:?}"));
+        assert_eq!(dealt.len(), 10);
+        // Pressing the positions where the PIN digits landed spells the
+        // PIN, wherever the shuffle put them.
+        let mut atm = atm;
+        for digit_key in PIN {
+            let position = atm
+                .keypad_layout()
+                .iter()
+                .position(|key| key == digit_key)
+                .expect("every digit key is dealt somewhere") as u8;
+            atm = Atm::transition(&atm, &Action::PressPosition(position)).0;
+        }
+        let atm = run(atm, &[Action::PressKey(Key::Enter)]).0;
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        // The next session gets a different deal.
+        let again = run(
+            atm,
+            &[Action::AuthTimeout, Action::SwipeCard(hash_pin(PIN))],
+        )
+        .0;
+        assert_ne!(again.keypad_layout(), fresh().keypad_layout());
+    }
+
+    #[test]
+    fn history_exports_as_csv() {
+        assert_eq!(Atm::new(100).history_csv(), "type,amount,timestamp\n");
+        let atm = run(
+            authenticated(100),
+            &[Action::InsertNote(10), Action::PressKey(Key::Enter)],
+        )
+        .0;
+        // Move the clock so the second row carries a real timestamp.
+        let atm = run(atm, &[Action::SetClock(1_000)]).0;
+        let (atm, _) = withdraw(authenticated_from(atm), &[Key::Three, Key::Zero]);
+        assert_eq!(
+            atm.history_csv(),
+            "type,amount,timestamp\ndeposit,10,0\nwithdrawal,30,1000\n"
+        );
+    }
+
+    #[test]
+    fn fee_then_round_rounds_the_net_request() {
+        let atm = authenticated(100)
+            .with_denominations(vec![5])
+            .with_round_down(true)
+            .with_withdrawal_fee(2)
+            .with_fee_order(FeeOrder::FeeThenRound);
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Seven]);
+        // round(17 − 2) = 15 handed over, announced as a round-down of
+        // the $17 request just like the fee-free path would.
+        assert_eq!(
+            effect,
+            Some(Effect::RoundedDown {
+                requested: 17,
+                dispensed: 15,
+            })
+        );
+        assert_eq!(atm.cash_inside, 85);
+        assert_eq!(atm.withdrawn_today, 17);
+    }
+
+    #[test]
+    fn round_then_fee_rounds_twice() {
+        let atm = authenticated(100)
+            .with_denominations(vec![5])
+            .with_round_down(true)
+            .with_withdrawal_fee(2)
+            .with_fee_order(FeeOrder::RoundThenFee);
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Seven]);
+        // round(17) = 15, minus the fee is 13, rounded again to 10 —
+        // and the shortfall is announced.
+        assert_eq!(
+            effect,
+            Some(Effect::RoundedDown {
+                requested: 17,
+                dispensed: 10,
+            })
+        );
+        assert_eq!(atm.cash_inside, 90);
+        assert_eq!(atm.withdrawn_today, 12);
+    }
+
+    #[test]
+    fn cancelling_a_deposit_returns_the_notes_uncredited() {
+        let card = hash_pin(PIN);
+        let atm = authenticated_from(Atm::new(100).with_account(card, 50));
+        let atm = run(atm, &[Action::InsertNote(10), Action::InsertNote(20)]).0;
+        let (atm, effect) = Atm::transition(&atm, &Action::CancelDeposit);
+        assert_eq!(effect, Some(Effect::ReturnNotes(vec![10, 20])));
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(atm.account_balance(card), Some(50));
+        // Outside a deposit the cancel is ignored.
+        let (_, effect) = Atm::transition(&atm, &Action::CancelDeposit);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn advancing_a_manual_clock_times_the_session_out() {
+        let clock = ManualClock::at(1_000);
+        let atm = Atm::new(100).with_clock(clock.clone());
+        let atm = atm.sync_clock().0;
+        let atm = authenticated_from(atm);
+        // Within the timeout the session survives a sync...
+        clock.advance(Atm::DEFAULT_IDLE_TIMEOUT - 1);
+        let atm = atm.sync_clock().0;
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        // ...one more second and it is abandoned.
+        clock.advance(1);
+        let atm = atm.sync_clock().0;
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+    }
+
+    #[test]
+    fn wrong_pin_reports_attempts_remaining() {
+        let atm = run(Atm::new(100), &[Action::SwipeCard(hash_pin(PIN))]).0;
+        let (atm, effect) = Atm::transition(&atm, &Action::EnterPin(vec![Key::Nine]));
+        assert_eq!(
+            effect,
+            Some(Effect::WrongPin {
+                attempts_remaining: 2
+            })
+        );
+        // The final failure reports zero as the machine locks.
+        let atm = run(
+            atm,
+            &[
+                Action::SwipeCard(hash_pin(PIN)),
+                Action::EnterPin(vec![Key::Nine]),
+                Action::SwipeCard(hash_pin(PIN)),
+            ],
+        )
+        .0;
+        let (atm, effect) = Atm::transition(&atm, &Action::EnterPin(vec![Key::Nine]));
+        assert_eq!(
+            effect,
+            Some(Effect::WrongPin {
+                attempts_remaining: 0
+            })
+        );
+        assert_eq!(atm.expected_pin_hash, Auth::Locked);
+    }
+
+    #[test]
+    fn current_attempt_exists_only_while_authenticating() {
+        let atm = run(
+            Atm::new(100),
+            &[
+                Action::SwipeCard(hash_pin(PIN)),
+                Action::PressKey(Key::One),
+                Action::PressKey(Key::Two),
+            ],
+        )
+        .0;
+        assert_eq!(atm.current_attempt(), Some(&[Key::One, Key::Two][..]));
+        // Amount digits while authenticated are not a PIN attempt.
+        let atm = run(
+            authenticated(100),
+            &[Action::PressKey(Key::Three), Action::PressKey(Key::Zero)],
+        )
+        .0;
+        assert_eq!(atm.current_attempt(), None);
+        assert_eq!(Atm::new(100).current_attempt(), None);
+    }
+
+    #[test]
+    fn jammed_dispenser_fails_withdrawals_until_cleared() {
+        let atm = run(Atm::new(100), &[Action::JamDispenser]).0;
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Three, Key::Zero]);
+        assert_eq!(effect, Some(Effect::DispenserJam));
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        // Customers cannot clear a jam...
+        let atm = run(atm, &[Action::ClearJam]).0;
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Three, Key::Zero]);
+        assert_eq!(effect, Some(Effect::DispenserJam));
+        // ...an operator can, and service resumes.
+        let atm = run(
+            atm,
+            &[
+                Action::MaintenanceKey(true),
+                Action::ClearJam,
+                Action::MaintenanceKey(false),
+            ],
+        )
+        .0;
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Three, Key::Zero]);
+        assert!(matches!(effect, Some(Effect::Dispensed { amount: 30, .. })));
+        assert_eq!(atm.cash_inside, 70);
+    }
+
+    #[test]
+    fn a_custom_rule_can_forbid_odd_amounts() {
+        struct EvenAmountsOnly;
+
+        impl WithdrawalRule for EvenAmountsOnly {
+            fn check(&self, amount: u64, _state: &Atm) -> Result<(), AtmError> {
+                if amount.is_multiple_of(2) {
+                    Ok(())
+                } else {
+                    Err(AtmError::InvalidAmount)
+                }
+            }
+        }
+
+        let atm = Atm::new(100).with_rule(EvenAmountsOnly);
+        assert_eq!(
+            authenticated_from(atm.clone()).preview_withdrawal(15),
+            Err(AtmError::InvalidAmount)
+        );
+        let (atm, effect) = withdraw(
+            authenticated_from(atm),
+            &[Key::One, Key::Five],
+        );
+        assert_eq!(effect, None);
+        assert_eq!(atm.cash_inside, 100);
+        // Even amounts pass the rule and dispense as usual.
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::One, Key::Six]);
+        assert!(effect.is_some());
+        assert_eq!(atm.cash_inside, 84);
+    }
+
+    #[test]
+    fn exact_bill_requests_dispense_from_stock() {
+        let atm = authenticated_from(Atm::with_inventory(HashMap::from([(5, 10), (20, 2)])));
+        let (atm, effect) = Atm::transition(
+            &atm,
+            &Action::WithdrawBills {
+                denomination: 5,
+                count: 4,
+            },
+        );
+        assert_eq!(
+            effect,
+            Some(Effect::Dispensed {
+                amount: 20,
+                bills: vec![5, 5, 5, 5],
+                balance_after: 70,
+            })
+        );
+        assert_eq!(atm.cash_inside, 70);
+        // Asking for more twenties than the drawer holds is refused.
+        let (atm, effect) = Atm::transition(
+            &authenticated_from(atm),
+            &Action::WithdrawBills {
+                denomination: 20,
+                count: 3,
+            },
+        );
+        assert_eq!(effect, None);
+        assert_eq!(atm.cash_inside, 70);
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+    }
+
+    #[test]
+    fn fingerprints_separate_states_that_differ() {
+        // Equal states, equal fingerprints.
+        assert_eq!(
+            Atm::new(100).state_fingerprint(),
+            Atm::new(100).state_fingerprint()
+        );
+        assert_eq!(
+            authenticated(100).state_fingerprint(),
+            authenticated(100).state_fingerprint()
+        );
+        // Obviously different states tell apart.
+        assert_ne!(
+            Atm::new(100).state_fingerprint(),
+            Atm::new(99).state_fingerprint()
+        );
+        assert_ne!(
+            Atm::new(100).state_fingerprint(),
+            authenticated(100).state_fingerprint()
+        );
+    }
+
+    #[test]
+    fn a_hold_blocks_withdrawals_until_released() {
+        let atm = authenticated(100);
+        let atm = Atm::transition(&atm, &Action::HoldFunds(80)).0;
+        // Only $20 is uncommitted, so $30 is refused...
+        assert_eq!(
+            atm.preview_withdrawal(30),
+            Err(AtmError::MachineOutOfCash)
+        );
+        let (atm, effect) = withdraw(atm, &[Key::Three, Key::Zero]);
+        assert_eq!(effect, None);
+        assert_eq!(atm.cash_inside, 100);
+        // ...until the hold is released.
+        let atm = Atm::transition(&atm, &Action::ReleaseHold).0;
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Three, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.cash_inside, 70);
+    }
+
+    #[test]
+    fn holds_cannot_encumber_the_reserve() {
+        let atm = authenticated(100).with_min_reserve(5);
+        // 98 of 100 would leave the float exposed at capture; refused.
+        let atm = Atm::transition(&atm, &Action::HoldFunds(98)).0;
+        let atm = Atm::transition(&atm, &Action::CaptureHold).0;
+        assert_eq!(atm.cash_inside, 100);
+        // 95 is the most the reserve allows, and capture honours it.
+        let atm = Atm::transition(&authenticated(100).with_min_reserve(5), &Action::HoldFunds(95)).0;
+        let atm = Atm::transition(&atm, &Action::CaptureHold).0;
+        assert_eq!(atm.cash_inside, 5);
+    }
+
+    #[test]
+    fn local_holds_leave_the_usd_pool_alone() {
+        let card = hash_pin(PIN);
+        // A local session ties up $80 of the local pool...
+        let atm = run(
+            authenticated(100),
+            &[Action::HoldFunds(80), Action::AuthTimeout],
+        )
+        .0;
+        let atm = atm.with_usd_cash(50).with_foreign_card(card);
+        // ...but the foreign pool is unencumbered: $40 still comes out.
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Four, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.usd_inside(), 10);
+        assert_eq!(atm.cash_inside, 100);
+        // The local hold survives the foreign session and still captures.
+        let atm = Atm::transition(&atm, &Action::CaptureHold).0;
+        assert_eq!(atm.cash_inside, 20);
+    }
+
+    #[test]
+    fn capturing_a_hold_settles_the_cash() {
+        let atm = authenticated(100);
+        let atm = Atm::transition(&atm, &Action::HoldFunds(40)).0;
+        let atm = Atm::transition(&atm, &Action::CaptureHold).0;
+        assert_eq!(atm.cash_inside, 60);
+        assert_eq!(atm.transaction_count(), 1);
+        // The session is still open, and the settled cash shows up on
+        // the statement like any withdrawal.
+        let (_, effect) = Atm::transition(&atm, &Action::MiniStatement(1));
+        assert_eq!(
+            effect,
+            Some(Effect::Statement(vec![Transaction::Withdrawal {
+                amount: 40,
+                at: 0,
+            }]))
+        );
+    }
+
+    #[test]
+    fn atm_steps_behind_a_trait_object() {
+        let mut machine: Box<dyn DynStateMachine> = Box::new(Atm::new(100));
+        assert!(!machine.accepting());
+        machine.step(&Action::SwipeCard(hash_pin(PIN)));
+        machine.step(&Action::EnterPin(PIN.to_vec()));
+        assert!(machine.accepting());
+        // Effects come through the object too.
+        let effect = [Key::Three, Key::Zero, Key::Enter]
+            .into_iter()
+            .filter_map(|k| machine.step(&Action::PressKey(k)))
+            .next();
+        assert!(matches!(effect, Some(Effect::Dispensed { amount: 30, .. })));
+    }
+
+    #[test]
+    fn card_status_tracks_swipe_and_eject() {
+        let (_, effect) = Atm::transition(&Atm::new(100), &Action::CardStatus);
+        assert_eq!(effect, Some(Effect::CardPresent(false)));
+        let atm = run(Atm::new(100), &[Action::SwipeCard(hash_pin(PIN))]).0;
+        let (atm, effect) = Atm::transition(&atm, &Action::CardStatus);
+        assert_eq!(effect, Some(Effect::CardPresent(true)));
+        let atm = run(atm, &[Action::TakeCard]).0;
+        let (_, effect) = Atm::transition(&atm, &Action::CardStatus);
+        assert_eq!(effect, Some(Effect::CardPresent(false)));
+    }
+
+    #[test]
+    fn default_authentication_charges_nothing() {
+        let card = hash_pin(PIN);
+        let atm = authenticated_from(Atm::new(100).with_account(card, 250));
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(atm.account_balance(card), Some(250));
+    }
+
+    #[test]
+    fn training_mode_waives_the_auth_fee() {
+        let card = hash_pin(PIN);
+        let atm = run(
+            Atm::new(100)
+                .with_account(card, 250)
+                .with_on_auth(AuthEffect::ChargeFee(2)),
+            &[
+                Action::MaintenanceKey(true),
+                Action::SetTrainingMode(true),
+                Action::MaintenanceKey(false),
+            ],
+        )
+        .0;
+        let atm = authenticated_from(atm);
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        // The session opens, but no real balance is charged.
+        assert_eq!(atm.account_balance(card), Some(250));
+    }
+
+    #[test]
+    fn auth_fee_is_booked_against_the_account() {
+        let card = hash_pin(PIN);
+        let atm = authenticated_from(
+            Atm::new(100)
+                .with_account(card, 250)
+                .with_on_auth(AuthEffect::ChargeFee(2)),
+        );
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        assert_eq!(atm.account_balance(card), Some(248));
+        // The fee is bookkeeping, not bills: machine cash is untouched.
+        assert_eq!(atm.cash_inside, 100);
+    }
+
+    #[test]
+    fn balance_receipt_ends_the_session_without_cash() {
+        let card = hash_pin(PIN);
+        let atm = authenticated_from(Atm::new(100).with_account(card, 250));
+        let (atm, effect) = Atm::transition(&atm, &Action::BalanceReceipt);
+        assert_eq!(effect, Some(Effect::BalancePrinted { balance: Some(250) }));
+        assert_eq!(atm.cash_inside, 100);
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+        // Cards without a registered account get a receipt saying so.
+        let (_, effect) = Atm::transition(&authenticated(100), &Action::BalanceReceipt);
+        assert_eq!(effect, Some(Effect::BalancePrinted { balance: None }));
+        // Outside a session the request is ignored.
+        let (_, effect) = Atm::transition(&Atm::new(100), &Action::BalanceReceipt);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn locked_machine_absorbs_customer_actions() {
+        let customer_actions = [
+            Action::SwipeCard(hash_pin(PIN)),
+            Action::TapCard(hash_pin(PIN)),
+            Action::EnterPin(PIN.to_vec()),
+            Action::PressKey(Key::Five),
+            Action::PressKey(Key::Enter),
+            Action::InsertNote(10),
+        ];
+        let locked = fail_pin_once(Atm::new(100).with_max_attempts(1));
+        assert!(is_absorbing::<Atm>(&locked, &customer_actions));
+        // A waiting machine is not stuck: a swipe moves it.
+        assert!(!is_absorbing::<Atm>(&Atm::new(100), &customer_actions));
+        // Neither is the locked machine once the operator's key is listed.
+        let mut with_operator = customer_actions.to_vec();
+        with_operator.push(Action::MaintenanceKey(true));
+        assert!(!is_absorbing::<Atm>(&locked, &with_operator));
+    }
+
+    #[test]
+    fn foreign_cards_draw_the_usd_pool() {
+        let card = hash_pin(PIN);
+        let atm = Atm::new(100).with_usd_cash(50).with_foreign_card(card);
+        assert_eq!(atm.session_currency(), Currency::Local);
+        let atm = authenticated_from(atm);
+        assert_eq!(atm.session_currency(), Currency::Usd);
+        let (atm, effect) = withdraw(atm, &[Key::Two, Key::Zero]);
+        assert!(matches!(
+            effect,
+            Some(Effect::Dispensed {
+                amount: 20,
+                balance_after: 30,
+                ..
+            })
+        ));
+        assert_eq!(atm.usd_inside(), 30);
+        assert_eq!(atm.cash_inside, 100);
+        // The USD pool alone bounds a foreign withdrawal — and the
+        // preview agrees with the real thing.
+        assert_eq!(
+            authenticated_from(atm.clone()).preview_withdrawal(40),
+            Err(AtmError::MachineOutOfCash)
+        );
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Four, Key::Zero]);
+        assert_eq!(effect, None);
+        assert_eq!(atm.usd_inside(), 30);
+    }
+
+    #[test]
+    fn local_cards_leave_the_usd_pool_alone() {
+        let atm = Atm::new(100).with_usd_cash(50);
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Two, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.cash_inside, 80);
+        assert_eq!(atm.usd_inside(), 50);
+    }
+
+    #[test]
+    fn advance_matches_next_state_step_for_step() {
+        let mut actions = vec![Action::SwipeCard(hash_pin(PIN))];
+        actions.extend(PIN.iter().map(|k| Action::PressKey(*k)));
+        actions.push(Action::PressKey(Key::Enter));
+        actions.extend([Key::Three, Key::Zero, Key::Enter].map(Action::PressKey));
+        // Ignored input and timers take the fast and slow paths too.
+        actions.push(Action::PressKey(Key::One));
+        actions.push(Action::Tick);
+
+        let mut mutated = Atm::new(100);
+        let mut pure = Atm::new(100);
+        for action in &actions {
+            let (next, expected_effect) = Atm::transition(&pure, action);
+            pure = next;
+            assert_eq!(mutated.advance(action), expected_effect);
+            assert_eq!(mutated, pure);
+        }
+    }
+
+    #[test]
+    fn advance_matches_next_state_through_a_deposit() {
+        let actions = [
+            Action::SwipeCard(hash_pin(PIN)),
+            Action::EnterPin(PIN.to_vec()),
+            // A bare-`Enter` slip, then notes with an ignored digit between.
+            Action::PressKey(Key::Enter),
+            Action::InsertNote(10),
+            Action::PressKey(Key::Five),
+            Action::InsertNote(20),
+            Action::PressKey(Key::Enter),
+        ];
+        let mut mutated = Atm::new(100);
+        let mut pure = Atm::new(100);
+        for action in &actions {
+            let (next, expected_effect) = Atm::transition(&pure, action);
+            pure = next;
+            assert_eq!(mutated.advance(action), expected_effect);
+            assert_eq!(mutated, pure);
+        }
+        assert_eq!(mutated.cash_inside, 130);
+    }
+
+    #[test]
+    fn hash_keys_matches_the_original_string_digest() {
+        // The allocation went away; the digest must not. Compare against
+        // the original implementation — hashing the digit `String` —
+        // so hashes minted before the change keep authenticating.
+        let mut hasher = DefaultHasher::new();
+        String::from("1234").hash(&mut hasher);
+        assert_eq!(hash_keys(PIN), hasher.finish());
+        let mut hasher = DefaultHasher::new();
+        String::new().hash(&mut hasher);
+        assert_eq!(hash_keys(&[]), hasher.finish());
+        // Non-digit keys never contributed to the hash; they still don't.
+        assert_eq!(
+            hash_keys(&[Key::One, Key::Dot, Key::Two, Key::Enter]),
+            hash_keys(&[Key::One, Key::Two])
+        );
+    }
+
+    #[test]
+    fn simulation_is_deterministic_per_seed() {
+        let a = simulate_random(Atm::new(1_000), 500, 42);
+        let b = simulate_random(Atm::new(1_000), 500, 42);
+        assert_eq!(a, b);
+        // The run did something: at least the clock or counters moved.
+        assert_ne!(a, Atm::new(1_000));
+    }
+
+    #[test]
+    fn operator_balance_reports_cash_and_bills() {
+        let atm = run(
+            Atm::with_inventory(HashMap::from([(20, 5), (10, 10)])),
+            &[Action::MaintenanceKey(true)],
+        )
+        .0;
+        let (_, effect) = Atm::transition(&atm, &Action::OperatorBalance);
+        assert_eq!(
+            effect,
+            Some(Effect::MachineCash {
+                total: 200,
+                inventory: HashMap::from([(20, 5), (10, 10)]),
+            })
+        );
+    }
+
+    #[test]
+    fn operator_balance_requires_supervisor() {
+        let (_, effect) = Atm::transition(&Atm::new(100), &Action::OperatorBalance);
+        assert_eq!(effect, None);
+    }
+
+    #[test]
+    fn custom_digit_map_rewires_the_keypad() {
+        let mut map = Key::standard_digit_map();
+        // Swap the digits behind the 1 and 9 keys.
+        map.insert(Key::One, 9);
+        map.insert(Key::Nine, 1);
+        let atm = Atm::new(100).with_digit_map(map);
+        // The card expects PIN 1234, and digit 1 now lives on the 9 key.
+        let atm = run(
+            atm,
+            &[
+                Action::SwipeCard(hash_pin(PIN)),
+                Action::EnterPin(vec![Key::Nine, Key::Two, Key::Three, Key::Four]),
+            ],
+        )
+        .0;
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        // Keying "90" on this pad means $10.
+        let (atm, effect) = withdraw(atm, &[Key::Nine, Key::Zero]);
+        assert_eq!(atm.cash_inside, 90);
+        assert!(matches!(effect, Some(Effect::Dispensed { amount: 10, .. })));
+    }
+
+    #[test]
+    fn bare_enter_after_authentication_keeps_the_session() {
+        let atm = authenticated(100);
+        let (atm, effect) = Atm::transition(&atm, &Action::PressKey(Key::Enter));
+        assert_eq!(effect, None);
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        assert!(atm.keystroke_register.is_empty());
+        // The slip costs nothing: a normal withdrawal still goes through.
+        let (atm, effect) = withdraw(atm, &[Key::One, Key::Zero]);
+        assert!(effect.is_some());
+        assert_eq!(atm.cash_inside, 90);
+    }
+
+    #[test]
+    fn operator_set_daily_limit_caps_withdrawals_and_survives_new_day() {
+        let atm = run(
+            Atm::new(1_000),
+            &[
+                Action::MaintenanceKey(true),
+                Action::SetDailyLimit(50),
+                Action::MaintenanceKey(false),
+            ],
+        )
+        .0;
+        // $60 busts the lowered allowance straight away.
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Six, Key::Zero]);
+        assert_eq!(effect, None);
+        assert_eq!(atm.cash_inside, 1_000);
+        // $40 fits...
+        let (atm, effect) = withdraw(authenticated_from(atm), &[Key::Four, Key::Zero]);
+        assert!(effect.is_some());
+        // ...and the limit itself survives the midnight rollover.
+        let atm = run(atm, &[Action::NewDay]).0;
+        assert_eq!(atm.config().daily_limit, 50);
+        assert_eq!(atm.withdrawn_today, 0);
+    }
+
+    #[test]
+    fn setting_the_daily_limit_requires_supervisor() {
+        let atm = run(Atm::new(1_000), &[Action::SetDailyLimit(50)]).0;
+        assert_eq!(atm.config().daily_limit, Atm::DEFAULT_DAILY_LIMIT);
+    }
+
+    #[test]
+    fn status_codes_pack_state_and_flags() {
+        // A flush, powered, waiting machine is all zeroes.
+        assert_eq!(Atm::new(1_000).status_code(), 0);
+        // Below one maximum withdrawal the low-cash bit comes on.
+        assert_eq!(Atm::new(100).status_code(), 0b1_0000);
+        // Authenticated state in the low bits.
+        assert_eq!(authenticated(1_000).status_code(), 2);
+        // Lockout sets both the state and the dedicated flag.
+        let locked = fail_pin_once(Atm::new(1_000).with_max_attempts(1));
+        assert_eq!(locked.status_code(), 0b10_0101);
+        // Power loss reads as out of service.
+        let dark = run(Atm::new(1_000), &[Action::PowerLoss]).0;
+        assert_eq!(dark.status_code(), 0b1000);
+    }
+
+    #[test]
+    fn rapid_repeated_swipes_trip_the_fraud_detector() {
+        let card = hash_pin(PIN);
+        let mut atm = Atm::new(100);
+        // Three swipe-and-abandon rounds inside the window are tolerated...
+        for _ in 0..3 {
+            atm = run(atm, &[Action::SwipeCard(card), Action::AuthTimeout]).0;
+        }
+        // ...but a fourth swipe of the same card smells like fraud.
+        let (atm, effect) = Atm::transition(&atm, &Action::SwipeCard(card));
+        assert_eq!(effect, Some(Effect::SuspiciousActivity));
+        assert_eq!(atm.expected_pin_hash, Auth::CardRejected);
+    }
+
+    #[test]
+    fn spread_out_swipes_are_not_suspicious() {
+        let card = hash_pin(PIN);
+        let mut atm = Atm::new(100);
+        for _ in 0..5 {
+            atm = run(atm, &[Action::SwipeCard(card), Action::AuthTimeout]).0;
+            // Let the swipe window pass before the next attempt.
+            for _ in 0..Atm::DEFAULT_SWIPE_WINDOW {
+                atm = Atm::transition(&atm, &Action::Tick).0;
+            }
+        }
+        let (atm, effect) = Atm::transition(&atm, &Action::SwipeCard(card));
+        assert_eq!(effect, None);
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticating(card));
+    }
+
+    #[test]
+    fn approved_higher_limit_works_once() {
+        let atm = run(
+            Atm::new(2_000),
+            &[
+                Action::MaintenanceKey(true),
+                Action::ApproveHigherLimit(1_000),
+                Action::MaintenanceKey(false),
+            ],
+        )
+        .0;
+        // $600 is over the standing $500 limit but under the approved one.
+        let (atm, effect) = withdraw(
+            authenticated_from(atm),
+            &[Key::Six, Key::Zero, Key::Zero],
+        );
+        assert!(effect.is_some());
+        assert_eq!(atm.cash_inside, 1_400);
+        // The approval was one-time: a second $600 is refused.
+        let (atm, effect) = withdraw(
+            authenticated_from(atm),
+            &[Key::Six, Key::Zero, Key::Zero],
+        );
+        assert_eq!(effect, None);
+        assert_eq!(atm.cash_inside, 1_400);
+    }
+
+    #[test]
+    fn approving_a_limit_requires_supervisor() {
+        let atm = run(Atm::new(2_000), &[Action::ApproveHigherLimit(1_000)]).0;
+        let (atm, effect) = withdraw(
+            authenticated_from(atm),
+            &[Key::Six, Key::Zero, Key::Zero],
+        );
+        assert_eq!(effect, None);
+        assert_eq!(atm.cash_inside, 2_000);
+    }
+
+    #[test]
+    fn expected_hash_is_readable_after_a_swipe() {
+        let atm = Atm::new(100);
+        assert_eq!(atm.expected_pin_hash(), None);
+        let atm = run(atm, &[Action::SwipeCard(hash_pin(PIN))]).0;
+        assert_eq!(atm.expected_pin_hash(), Some(hash_pin(PIN)));
+        // Once authenticated the expectation follows the session card.
+        let atm = authenticated_from(atm);
+        assert_eq!(atm.expected_pin_hash(), Some(hash_pin(PIN)));
+    }
+
+    #[test]
+    fn enter_pin_authenticates_in_one_action() {
+        let atm = run(
+            Atm::new(100),
+            &[
+                Action::SwipeCard(hash_pin(PIN)),
+                Action::EnterPin(PIN.to_vec()),
+            ],
+        )
+        .0;
+        assert_eq!(atm.expected_pin_hash, Auth::Authenticated);
+        // Before a swipe a batched attempt is ignored like any keypress.
+        let atm = run(Atm::new(100), &[Action::EnterPin(PIN.to_vec())]).0;
+        assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+    }
+
+    #[test]
+    fn different_hashes_authenticating_are_the_same_kind() {
+        let a = run(Atm::new(100), &[Action::SwipeCard(1)]).0;
+        let b = run(Atm::new(100), &[Action::SwipeCard(2)]).0;
+        assert!(a.same_auth_kind(&b));
+        // A waiting machine is a different kind from an authenticating one.
+        assert!(!a.same_auth_kind(&Atm::new(100)));
+    }
+
+    #[test]
+    fn mini_statement_lists_the_last_entries() {
+        let mut atm = Atm::new(500);
+        for amount in [10, 20, 30, 40, 50] {
+            let actions: Vec<Action> = withdrawal_keys(amount)
+                .into_iter()
+                .map(Action::PressKey)
+                .collect();
+            atm = run(authenticated_from(atm), &actions).0;
+        }
+        let atm = authenticated_from(atm);
+        let (next, effect) = Atm::transition(&atm, &Action::MiniStatement(3));
+        assert_eq!(
+            effect,
+            Some(Effect::Statement(vec![
+                Transaction::Withdrawal { amount: 30, at: 0 },
+                Transaction::Withdrawal { amount: 40, at: 0 },
+                Transaction::Withdrawal { amount: 50, at: 0 },
+            ]))
+        );
+        // The statement is read-only: the machine is unchanged.
+        assert_eq!(next, atm);
+        // Asking for more than exists clamps to the whole history.
+        let (_, effect) = Atm::transition(&atm, &Action::MiniStatement(99));
+        assert!(matches!(effect, Some(Effect::Statement(all)) if all.len() == 5));
+    }
+
+    #[test]
+    fn inventory_totals_the_bills() {
+        let atm = Atm::with_inventory(HashMap::from([(20, 5), (10, 10)]));
+        assert_eq!(atm.cash_inside, 200);
+        // The denominations come from the bills, largest first.
+        assert_eq!(atm.config().denominations, vec![20, 10]);
+    }
+
+    #[test]
+    fn dispensing_is_bounded_by_the_inventory() {
+        // Only one 20 on hand: a $40 request has to fall back to tens.
+        let atm = authenticated_from(Atm::with_inventory(HashMap::from([(20, 1), (10, 10)])));
+        let (_, effect) = withdraw(atm, &[Key::Four, Key::Zero]);
+        assert_eq!(
+            effect,
+            Some(Effect::Dispensed {
+                amount: 40,
+                bills: vec![20, 10, 10],
+                balance_after: 80,
+            })
+        );
+    }
+}
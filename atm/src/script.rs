@@ -0,0 +1,127 @@
+//! A small line language for driver scripts: one action per line.
+//!
+//! The verbs mirror the [`Action`] variants — `swipe 42`, `pin 1234`,
+//! `key 5`, `key enter`, `note 10`, `tick`, `maintenance on` and so on —
+//! so a text file can drive a machine without writing Rust.
+
+use std::fmt;
+
+use crate::{Action, Key, Language};
+
+/// Why a script line failed to parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The verb is not one the script language knows.
+    UnknownCommand(String),
+    /// The verb's argument was missing or malformed.
+    BadArgument(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownCommand(verb) => write!(f, "unknown command `{verb}`"),
+            ParseError::BadArgument(line) => write!(f, "bad argument in `{line}`"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl TryFrom<&str> for Action {
+    type Error = ParseError;
+
+    fn try_from(line: &str) -> Result<Self, ParseError> {
+        let bad = || ParseError::BadArgument(line.trim().to_string());
+        let mut words = line.split_whitespace();
+        let verb = words.next().unwrap_or("");
+        let arg = words.next();
+        let number = || -> Result<u64, ParseError> {
+            arg.and_then(|word| word.parse().ok()).ok_or_else(bad)
+        };
+        match verb {
+            "swipe" => Ok(Action::SwipeCard(number()?)),
+            "tap" => Ok(Action::TapCard(number()?)),
+            "key" => match arg {
+                Some("dot") | Some(".") => Ok(Action::PressKey(Key::Dot)),
+                Some("enter") => Ok(Action::PressKey(Key::Enter)),
+                Some(word) => {
+                    let digit = word.parse().ok().filter(|d| *d < 10).ok_or_else(bad)?;
+                    Ok(Action::PressKey(Key::from_digit(digit).expect("digit is 0-9")))
+                }
+                None => Err(bad()),
+            },
+            "pin" => {
+                let keys = arg
+                    .ok_or_else(bad)?
+                    .chars()
+                    .map(|c| c.to_digit(10).and_then(Key::from_digit))
+                    .collect::<Option<Vec<Key>>>()
+                    .ok_or_else(bad)?;
+                Ok(Action::EnterPin(keys))
+            }
+            "note" => Ok(Action::InsertNote(number()?)),
+            "tick" => Ok(Action::Tick),
+            "setclock" => Ok(Action::SetClock(number()?)),
+            "newday" => Ok(Action::NewDay),
+            "take" => Ok(Action::TakeCard),
+            "timeout" => Ok(Action::AuthTimeout),
+            "powerloss" => Ok(Action::PowerLoss),
+            "powerrestore" => Ok(Action::PowerRestore),
+            "maintenance" => match arg {
+                Some("on") => Ok(Action::MaintenanceKey(true)),
+                Some("off") => Ok(Action::MaintenanceKey(false)),
+                _ => Err(bad()),
+            },
+            "lang" => match arg {
+                Some("en") => Ok(Action::SetLanguage(Language::English)),
+                Some("es") => Ok(Action::SetLanguage(Language::Spanish)),
+                _ => Err(bad()),
+            },
+            other => Err(ParseError::UnknownCommand(other.to_string())),
+        }
+    }
+}
+
+/// Parse a whole script up front, so a typo on line 30 surfaces before
+/// line 1 runs. Returns every action, or the (zero-based) index of the
+/// first bad line and what was wrong with it.
+pub fn validate_script(lines: &[&str]) -> Result<Vec<Action>, (usize, ParseError)> {
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| Action::try_from(*line).map_err(|error| (index, error)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_script_parses_into_actions() {
+        let actions = validate_script(&["swipe 42", "pin 1234", "key 3", "key 0", "key enter"])
+            .expect("every line is well-formed");
+        assert_eq!(actions.len(), 5);
+        assert_eq!(actions[0], Action::SwipeCard(42));
+        assert_eq!(
+            actions[1],
+            Action::EnterPin(vec![Key::One, Key::Two, Key::Three, Key::Four])
+        );
+        assert_eq!(actions[4], Action::PressKey(Key::Enter));
+    }
+
+    #[test]
+    fn first_bad_line_is_reported_by_index() {
+        let result = validate_script(&["tick", "swipe fortytwo", "swipe 42"]);
+        assert_eq!(
+            result,
+            Err((1, ParseError::BadArgument("swipe fortytwo".to_string())))
+        );
+        let result = validate_script(&["frobnicate"]);
+        assert_eq!(
+            result,
+            Err((0, ParseError::UnknownCommand("frobnicate".to_string())))
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! Sharing one machine between threads, for server-style drivers.
+
+use std::sync::Mutex;
+
+use crate::{Action, Atm, StateMachine};
+
+/// A machine behind a lock, so several threads can drive it safely.
+///
+/// Each [`SharedAtm::apply`] is one atomic step: lock, transition via
+/// [`StateMachine::next_state`], store the result, and hand back a
+/// snapshot of the state just produced. The pure transition function
+/// does all the real work; this wrapper only serializes access to the
+/// single current state.
+pub struct SharedAtm {
+    inner: Mutex<Atm>,
+}
+
+impl SharedAtm {
+    pub fn new(atm: Atm) -> Self {
+        SharedAtm {
+            inner: Mutex::new(atm),
+        }
+    }
+
+    /// Apply `action` atomically and return the state it produced.
+    pub fn apply(&self, action: Action) -> Atm {
+        let mut state = self.inner.lock().expect("ATM lock poisoned");
+        *state = Atm::next_state(&state, &action);
+        state.clone()
+    }
+
+    /// The current state, without transitioning.
+    pub fn snapshot(&self) -> Atm {
+        self.inner.lock().expect("ATM lock poisoned").clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn threads_drive_one_machine_without_losing_steps() {
+        let shared = Arc::new(SharedAtm::new(Atm::new(100)));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        shared.apply(Action::Tick);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("ticking thread should not panic");
+        }
+        // Every tick took the lock, so all 100 are in the final clock.
+        assert_eq!(shared.snapshot(), {
+            let mut expected = Atm::new(100);
+            for _ in 0..100 {
+                expected = Atm::next_state(&expected, &Action::Tick);
+            }
+            expected
+        });
+    }
+
+    #[test]
+    fn apply_returns_the_state_it_produced() {
+        let shared = SharedAtm::new(Atm::new(100));
+        let seen = shared.apply(Action::Tick);
+        assert_eq!(seen, shared.snapshot());
+    }
+}
@@ -0,0 +1,84 @@
+//! Persistence for machine state, so the ATM can survive restarts.
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::Atm;
+
+/// Somewhere machine state can be saved to and restored from.
+///
+/// Implementations are best-effort: `save` swallows I/O errors and `load`
+/// answers `None` both for "nothing saved yet" and "saved data unreadable" —
+/// either way the caller starts from a fresh machine.
+pub trait StateStore {
+    fn save(&self, atm: &Atm);
+    fn load(&self) -> Option<Atm>;
+}
+
+/// Stores the machine as a bincode blob in a file.
+///
+/// Note that a custom PIN hasher is not persisted; a loaded machine uses
+/// the default hasher until one is re-injected.
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileStore { path: path.into() }
+    }
+}
+
+impl StateStore for FileStore {
+    fn save(&self, atm: &Atm) {
+        if let Ok(bytes) = bincode::serialize(atm) {
+            let _ = fs::write(&self.path, bytes);
+        }
+    }
+
+    fn load(&self) -> Option<Atm> {
+        bincode::deserialize(&fs::read(&self.path).ok()?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// A store backed by nothing but memory, for tests.
+    struct MemoryStore(RefCell<Option<Atm>>);
+
+    impl StateStore for MemoryStore {
+        fn save(&self, atm: &Atm) {
+            *self.0.borrow_mut() = Some(atm.clone());
+        }
+
+        fn load(&self) -> Option<Atm> {
+            self.0.borrow().clone()
+        }
+    }
+
+    #[test]
+    fn memory_store_round_trips_a_machine() {
+        let store = MemoryStore(RefCell::new(None));
+        assert_eq!(store.load(), None);
+
+        let atm = Atm::new(75).with_daily_limit(300);
+        store.save(&atm);
+        assert_eq!(store.load(), Some(atm));
+    }
+
+    #[test]
+    fn file_store_round_trips_a_machine() {
+        let path = std::env::temp_dir().join("atm-file-store-test.bin");
+        let store = FileStore::new(&path);
+
+        let atm = Atm::new(120).with_max_withdrawal(60);
+        store.save(&atm);
+        let loaded = store.load().expect("saved state should load");
+        assert_eq!(loaded, atm);
+
+        let _ = fs::remove_file(path);
+    }
+}
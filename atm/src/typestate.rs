@@ -0,0 +1,144 @@
+//! Compile-time session typing layered over the runtime machine.
+//!
+//! Each wrapper owns an [`Atm`] known to be in one authentication state,
+//! and only that state's operations exist on it: `withdraw` lives on
+//! [`AtmAuthenticated`] alone, so withdrawing from a waiting machine is
+//! a compile error rather than a silently ignored action. Transitions
+//! consume the wrapper and hand back the type for the state actually
+//! reached; whenever the machine falls out of the typed path (a refused
+//! card, a wrong PIN, a lockout) the raw [`Atm`] comes back instead, to
+//! be inspected or re-wrapped.
+
+// Both `Result` variants carry a whole machine — the error path is no
+// bigger than the success path, so boxing it would buy nothing.
+#![allow(clippy::result_large_err)]
+
+use crate::{withdrawal_keys, Action, Atm, Auth, Effect, Key};
+
+/// A machine waiting for a card.
+#[derive(Debug)]
+pub struct AtmWaiting(Atm);
+
+/// A machine with a card swiped, expecting PIN entry.
+#[derive(Debug)]
+pub struct AtmAuthenticating(Atm);
+
+/// A machine with a verified PIN, ready to dispense.
+#[derive(Debug)]
+pub struct AtmAuthenticated(Atm);
+
+impl AtmWaiting {
+    /// A fresh machine holding `cash` dollars.
+    pub fn new(cash: u64) -> Self {
+        AtmWaiting(Atm::new(cash))
+    }
+
+    /// Wrap an existing machine, provided it really is waiting; anything
+    /// mid-session (or locked) is handed straight back.
+    pub fn from_machine(atm: Atm) -> Result<Self, Atm> {
+        match atm.expected_pin_hash {
+            Auth::Waiting => Ok(AtmWaiting(atm)),
+            _ => Err(atm),
+        }
+    }
+
+    /// Swipe a card. A hotlisted or suspiciously re-swiped card is
+    /// refused, dropping back to the raw machine.
+    pub fn swipe(self, pin_hash: u64) -> Result<AtmAuthenticating, Atm> {
+        let (next, _) = Atm::transition(&self.0, &Action::SwipeCard(pin_hash));
+        match next.expected_pin_hash {
+            Auth::Authenticating(_) => Ok(AtmAuthenticating(next)),
+            _ => Err(next),
+        }
+    }
+
+    /// The runtime machine, for anything the typed surface doesn't cover.
+    pub fn into_inner(self) -> Atm {
+        self.0
+    }
+}
+
+impl AtmAuthenticating {
+    /// Key in the whole PIN and press `Enter`. A wrong PIN exits the
+    /// typed session — the machine handed back may be waiting again or
+    /// locked, depending on the attempt count.
+    pub fn enter_pin(self, pin: &[Key]) -> Result<AtmAuthenticated, Atm> {
+        let (next, _) = Atm::transition(&self.0, &Action::EnterPin(pin.to_vec()));
+        match next.expected_pin_hash {
+            Auth::Authenticated => Ok(AtmAuthenticated(next)),
+            _ => Err(next),
+        }
+    }
+
+    /// The runtime machine, for anything the typed surface doesn't cover.
+    pub fn into_inner(self) -> Atm {
+        self.0
+    }
+}
+
+impl AtmAuthenticated {
+    /// Key in `amount` dollars and press `Enter`. Every outcome — cash
+    /// dispensed, or the request refused by a limit or the cash on hand —
+    /// ends the session, so a waiting machine always comes back; the
+    /// effect says whether bills came with it.
+    pub fn withdraw(self, amount: u64) -> (AtmWaiting, Option<Effect>) {
+        let mut atm = self.0;
+        let mut last_effect = None;
+        for key in withdrawal_keys(amount) {
+            let (next, effect) = Atm::transition(&atm, &Action::PressKey(key));
+            atm = next;
+            if effect.is_some() {
+                last_effect = effect;
+            }
+        }
+        // The typed flow is one transaction per session: a machine
+        // configured to stay authenticated is logged out here so the
+        // wrapper's type keeps telling the truth.
+        if atm.expected_pin_hash == Auth::Authenticated {
+            atm.expected_pin_hash = Auth::Waiting;
+        }
+        (AtmWaiting(atm), last_effect)
+    }
+
+    /// The runtime machine, for anything the typed surface doesn't cover.
+    pub fn into_inner(self) -> Atm {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_pin;
+
+    const PIN: &[Key] = &[Key::One, Key::Two, Key::Three, Key::Four];
+
+    #[test]
+    fn typed_session_withdraws_end_to_end() {
+        let waiting = AtmWaiting::new(100);
+        let entering = waiting.swipe(hash_pin(PIN)).expect("fresh card is accepted");
+        let session = entering.enter_pin(PIN).expect("right PIN authenticates");
+        let (waiting, effect) = session.withdraw(30);
+        assert!(effect.is_some());
+        assert_eq!(waiting.into_inner().cash_inside(), 70);
+    }
+
+    #[test]
+    fn wrong_pin_exits_the_typed_session() {
+        let entering = AtmWaiting::new(100)
+            .swipe(hash_pin(PIN))
+            .expect("fresh card is accepted");
+        let machine = entering
+            .enter_pin(&[Key::Nine])
+            .expect_err("wrong PIN should not authenticate");
+        // The raw machine can be inspected and re-wrapped.
+        assert_eq!(machine.prompt(), "Please swipe your card");
+        assert!(AtmWaiting::from_machine(machine).is_ok());
+    }
+
+    #[test]
+    fn from_machine_rejects_mid_session_states() {
+        let mid_session = Atm::transition(&Atm::new(100), &Action::SwipeCard(hash_pin(PIN))).0;
+        assert!(AtmWaiting::from_machine(mid_session).is_err());
+    }
+}